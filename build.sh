@@ -27,6 +27,19 @@ wasm-tools component new "${SCRIPT_DIR}/target/wasm32-unknown-unknown/release/li
 cargo build -p lifecycle-host-http --release --target wasm32-unknown-unknown
 wasm-tools component new "${SCRIPT_DIR}/target/wasm32-unknown-unknown/release/lifecycle_host_http.wasm" -o "${SCRIPT_DIR}/lib/lifecycle-host-http.wasm"
 
+# lifecycle hosts for the interfaces each lifecycle exports in addition to the lifecycle
+
+valkey_host_features="audit,quotas,reconcile"
+cargo build -p lifecycle-host-cli --release --target wasm32-unknown-unknown --features "${valkey_host_features}"
+wasm-tools component new "${SCRIPT_DIR}/target/wasm32-unknown-unknown/release/lifecycle_host_cli.wasm" -o "${SCRIPT_DIR}/lib/lifecycle-host-cli-valkey.wasm"
+cargo build -p lifecycle-host-http --release --target wasm32-unknown-unknown --features "${valkey_host_features}"
+wasm-tools component new "${SCRIPT_DIR}/target/wasm32-unknown-unknown/release/lifecycle_host_http.wasm" -o "${SCRIPT_DIR}/lib/lifecycle-host-http-valkey.wasm"
+filesystem_host_features="archive,quotas"
+cargo build -p lifecycle-host-cli --release --target wasm32-unknown-unknown --features "${filesystem_host_features}"
+wasm-tools component new "${SCRIPT_DIR}/target/wasm32-unknown-unknown/release/lifecycle_host_cli.wasm" -o "${SCRIPT_DIR}/lib/lifecycle-host-cli-filesystem.wasm"
+cargo build -p lifecycle-host-http --release --target wasm32-unknown-unknown --features "${filesystem_host_features}"
+wasm-tools component new "${SCRIPT_DIR}/target/wasm32-unknown-unknown/release/lifecycle_host_http.wasm" -o "${SCRIPT_DIR}/lib/lifecycle-host-http-filesystem.wasm"
+
 # filesystem components
 
 cargo build -p filesystem-lifecycle --release --target wasm32-wasip2
@@ -35,6 +48,8 @@ cargo build -p filesystem-credential-store --release --target wasm32-wasip2
 cp "${SCRIPT_DIR}/target/wasm32-wasip2/release/filesystem_credential_store.wasm" "${SCRIPT_DIR}/lib/filesystem-credential-store.wasm"
 cargo build -p filesystem-credential-admin --release --target wasm32-wasip2
 cp "${SCRIPT_DIR}/target/wasm32-wasip2/release/filesystem_credential_admin.wasm" "${SCRIPT_DIR}/lib/filesystem-credential-admin.wasm"
+cargo build -p filesystem-read-only --release --target wasm32-wasip2
+cp "${SCRIPT_DIR}/target/wasm32-wasip2/release/filesystem_read_only.wasm" "${SCRIPT_DIR}/lib/filesystem-read-only.wasm"
 
 # valkey components
 
@@ -64,6 +79,8 @@ cargo build -p ops-router --release --target wasm32-unknown-unknown
 wasm-tools component new "${SCRIPT_DIR}/target/wasm32-unknown-unknown/release/ops_router.wasm" -o "${SCRIPT_DIR}/lib/test/ops-router.wasm"
 cargo build -p service-cli --release --target wasm32-wasip2
 cp "${SCRIPT_DIR}/target/wasm32-wasip2/release/service-cli.wasm" "${SCRIPT_DIR}/lib/test/service-cli.wasm"
+cargo build -p config-reader --release --target wasm32-wasip2
+cp "${SCRIPT_DIR}/target/wasm32-wasip2/release/config-reader.wasm" "${SCRIPT_DIR}/lib/test/config-reader.wasm"
 
 # stub components
 
@@ -94,12 +111,20 @@ wac compose -o "${SCRIPT_DIR}/lib/test/ops.wasm" \
     -d componentized:credential-store="${SCRIPT_DIR}/lib/${cred_store_type}-credential-store.wasm" \
     -d componentized:credential-config="${SCRIPT_DIR}/lib/credential-config.wasm" \
     -d componentized:filesystem-client="${SCRIPT_DIR}/lib/test/filesystem-client.wasm" \
+    -d componentized:filesystem-read-only="${SCRIPT_DIR}/lib/filesystem-read-only.wasm" \
     -d componentized:filesystem-ops="${SCRIPT_DIR}/lib/test/filesystem-ops.wasm" \
     -d componentized:keyvalue-client="${SCRIPT_DIR}/lib/test/keyvalue-client.wasm" \
     -d componentized:keyvalue-ops="${SCRIPT_DIR}/lib/test/keyvalue-ops.wasm" \
     -d componentized:ops-router="${SCRIPT_DIR}/lib/test/ops-router.wasm" \
     "${SCRIPT_DIR}/tests/ops.wac"
 
+wac compose -o "${SCRIPT_DIR}/lib/test/config-cache.wasm" \
+    -d componentized:logging="${SCRIPT_DIR}/lib/test/logging.wasm" \
+    -d componentized:credential-store="${SCRIPT_DIR}/lib/test/stub-credential-store.wasm" \
+    -d componentized:credential-config="${SCRIPT_DIR}/lib/credential-config.wasm" \
+    -d componentized:config-reader="${SCRIPT_DIR}/lib/test/config-reader.wasm" \
+    "${SCRIPT_DIR}/tests/config-cache.wac"
+
 wac compose -o "${SCRIPT_DIR}/lib/test/cli.wasm" \
     -d componentized:logging="${SCRIPT_DIR}/lib/logging.wasm" \
     -d componentized:cli="${SCRIPT_DIR}/lib/test/service-cli.wasm" \
@@ -112,14 +137,30 @@ wac compose -o "${SCRIPT_DIR}/lib/test/cli.wasm" \
 
 wac compose -o "${SCRIPT_DIR}/lib/test/host-cli-valkey.wasm" \
     -d componentized:logging="${SCRIPT_DIR}/lib/test/logging.wasm" \
-    -d componentized:lifecycle-host="${SCRIPT_DIR}/lib/lifecycle-host-cli.wasm" \
+    -d componentized:lifecycle-host="${SCRIPT_DIR}/lib/lifecycle-host-cli-valkey.wasm" \
     -d componentized:lifecycle="${SCRIPT_DIR}/lib/valkey-lifecycle.wasm" \
+    -d componentized:credential-store="${SCRIPT_DIR}/lib/valkey-credential-store.wasm" \
     -d componentized:credential-admin="${SCRIPT_DIR}/lib/valkey-credential-admin.wasm" \
-    "${SCRIPT_DIR}/tests/host.wac"
+    "${SCRIPT_DIR}/tests/host-valkey.wac"
 
 wac compose -o "${SCRIPT_DIR}/lib/test/host-http-valkey.wasm" \
     -d componentized:logging="${SCRIPT_DIR}/lib/test/logging.wasm" \
-    -d componentized:lifecycle-host="${SCRIPT_DIR}/lib/lifecycle-host-http.wasm" \
+    -d componentized:lifecycle-host="${SCRIPT_DIR}/lib/lifecycle-host-http-valkey.wasm" \
     -d componentized:lifecycle="${SCRIPT_DIR}/lib/valkey-lifecycle.wasm" \
+    -d componentized:credential-store="${SCRIPT_DIR}/lib/valkey-credential-store.wasm" \
     -d componentized:credential-admin="${SCRIPT_DIR}/lib/valkey-credential-admin.wasm" \
-    "${SCRIPT_DIR}/tests/host.wac"
+    "${SCRIPT_DIR}/tests/host-valkey.wac"
+
+wac compose -o "${SCRIPT_DIR}/lib/test/host-cli-filesystem.wasm" \
+    -d componentized:logging="${SCRIPT_DIR}/lib/test/logging.wasm" \
+    -d componentized:lifecycle-host="${SCRIPT_DIR}/lib/lifecycle-host-cli-filesystem.wasm" \
+    -d componentized:lifecycle="${SCRIPT_DIR}/lib/filesystem-lifecycle.wasm" \
+    -d componentized:credential-admin="${SCRIPT_DIR}/lib/filesystem-credential-admin.wasm" \
+    "${SCRIPT_DIR}/tests/host-filesystem.wac"
+
+wac compose -o "${SCRIPT_DIR}/lib/test/host-http-filesystem.wasm" \
+    -d componentized:logging="${SCRIPT_DIR}/lib/test/logging.wasm" \
+    -d componentized:lifecycle-host="${SCRIPT_DIR}/lib/lifecycle-host-http-filesystem.wasm" \
+    -d componentized:lifecycle="${SCRIPT_DIR}/lib/filesystem-lifecycle.wasm" \
+    -d componentized:credential-admin="${SCRIPT_DIR}/lib/filesystem-credential-admin.wasm" \
+    "${SCRIPT_DIR}/tests/host-filesystem.wac"
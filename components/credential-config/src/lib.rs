@@ -1,13 +1,205 @@
 #![no_main]
 
+use chrono::{DateTime, Utc};
 use componentized::services::credential_store::fetch;
+use exports::componentized::services::credential_cache::Guest as CredentialCache;
 use exports::wasi::config::store::{Error, Guest};
+use mapping::KeyMapping;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use wasi::clocks::{monotonic_clock, wall_clock};
 use wasi::config::store;
 
+mod mapping;
+
 const BINDING_ID_KEY: &str = "binding-id";
+const BINDING_IDS_KEY: &str = "binding-ids";
+const CACHE_TTL_KEY: &str = "cache-ttl-seconds";
+const CACHE_TTL_DEFAULT: u64 = 60;
+const EXPIRES_AT_KEY: &str = "expires-at";
+const LAYERS_KEY: &str = "layers";
+const CREDENTIAL_OVERRIDES_KEY: &str = "credential-overrides";
+const CREDENTIALS_LAYER: &str = "credentials";
+const CONFIG_LAYER: &str = "config";
+
+thread_local! {
+    /// Credentials fetched for each binding.
+    static CACHE: RefCell<HashMap<String, CachedCredentials>> = RefCell::new(HashMap::new());
+}
+
+/// Credentials are reused until the TTL has elapsed since they were fetched, or until the time in
+/// their `expires-at` credential has passed, whichever comes first.
+struct CachedCredentials {
+    credentials: Vec<(String, String)>,
+    /// Monotonic clock instant the credentials were fetched at.
+    fetched_at: u64,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl CachedCredentials {
+    fn new(credentials: Vec<(String, String)>) -> Self {
+        let expires_at = credentials
+            .iter()
+            .find(|(k, _)| k == EXPIRES_AT_KEY)
+            .map(|(_, v)| {
+                // an unreadable expiry is treated as expired, the credentials are fetched each use
+                DateTime::parse_from_rfc3339(v)
+                    .map(|e| e.with_timezone(&Utc))
+                    .unwrap_or(DateTime::<Utc>::MIN_UTC)
+            });
+        CachedCredentials {
+            credentials,
+            fetched_at: monotonic_clock::now(),
+            expires_at,
+        }
+    }
+
+    fn is_fresh(&self, ttl_seconds: u64) -> bool {
+        let age = monotonic_clock::now().saturating_sub(self.fetched_at);
+        if age >= ttl_seconds.saturating_mul(1_000_000_000) {
+            return false;
+        }
+        match self.expires_at {
+            None => true,
+            Some(expires_at) => {
+                let now = wall_clock::now();
+                DateTime::from_timestamp(now.seconds as i64, now.nanoseconds)
+                    .map(|now| now < expires_at)
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
 
 pub(crate) struct CredentialConfig {}
 
+impl CredentialConfig {
+    /// Bindings to return credentials for, each with the namespace its keys are prefixed with.
+    /// Credentials for the `binding-id` config value are not namespaced. The `binding-ids` config
+    /// value lists further bindings as `<namespace>=<binding-id>`, or as `<binding-id>` to use the
+    /// binding id as the namespace.
+    fn bindings(config: &[(String, String)]) -> Result<Vec<(Option<String>, String)>, Error> {
+        let mut bindings = vec![];
+        if let Some(binding_id) = find(config, BINDING_ID_KEY) {
+            bindings.push((None, binding_id));
+        }
+        if let Some(binding_ids) = find(config, BINDING_IDS_KEY) {
+            for binding in binding_ids.split(',').map(str::trim) {
+                let (namespace, binding_id) = binding.split_once('=').unwrap_or((binding, binding));
+                if namespace.is_empty() || binding_id.is_empty() {
+                    Err(Error::Upstream(format!(
+                        "Config '{}' must list '<namespace>=<binding-id>', got: {}",
+                        BINDING_IDS_KEY, binding
+                    )))?;
+                }
+                bindings.push((Some(namespace.to_string()), binding_id.to_string()));
+            }
+        }
+        if bindings.is_empty() {
+            Err(Error::Upstream(format!(
+                "Config must contain '{}' or '{}'",
+                BINDING_ID_KEY, BINDING_IDS_KEY
+            )))?;
+        }
+        Ok(bindings)
+    }
+
+    /// Seconds credentials are reused for, `0` fetches them on every lookup.
+    fn cache_ttl(config: &[(String, String)]) -> Result<u64, Error> {
+        match find(config, CACHE_TTL_KEY) {
+            None => Ok(CACHE_TTL_DEFAULT),
+            Some(ttl) => ttl.parse().map_err(|_| {
+                Error::Upstream(format!(
+                    "Config '{}' must be a number of seconds, got: {}",
+                    CACHE_TTL_KEY, ttl
+                ))
+            }),
+        }
+    }
+
+    /// Fetch credentials from the credential store, replacing any cached for the binding.
+    fn fetch_credentials(binding_id: &str) -> Result<Vec<(String, String)>, Error> {
+        let credentials: Vec<(String, String)> = fetch(binding_id)
+            .map_err(|e| Error::Upstream(e.to_string()))?
+            .iter()
+            .map(|c| (c.key.clone(), c.value.clone()))
+            .collect();
+        CACHE.with_borrow_mut(|cache| {
+            cache.insert(
+                binding_id.to_string(),
+                CachedCredentials::new(credentials.clone()),
+            )
+        });
+
+        Ok(credentials)
+    }
+
+    /// Combine the credentials with the upstream config, by default only credentials are returned.
+    /// The `layers` config value lists `credentials` and `config` in order of precedence, keys
+    /// missing from a layer fall through to the next. When credentials take precedence, the
+    /// `credential-overrides` config value limits the keys credentials may override to those
+    /// listed.
+    fn layer(
+        credentials: Vec<(String, String)>,
+        config: Vec<(String, String)>,
+    ) -> Result<Vec<(String, String)>, Error> {
+        let layers = find(&config, LAYERS_KEY).unwrap_or(String::from(CREDENTIALS_LAYER));
+        let overrides: Option<Vec<String>> = find(&config, CREDENTIAL_OVERRIDES_KEY)
+            .map(|overrides| overrides.split(',').map(|k| k.trim().to_string()).collect());
+        // keys configuring credential-config are not passed to clients
+        let config: Vec<(String, String)> = config
+            .iter()
+            .filter(|(k, _)| {
+                ![
+                    BINDING_ID_KEY,
+                    BINDING_IDS_KEY,
+                    CACHE_TTL_KEY,
+                    LAYERS_KEY,
+                    CREDENTIAL_OVERRIDES_KEY,
+                ]
+                .contains(&k.as_str())
+                    && !KeyMapping::is_mapping_key(k)
+            })
+            .cloned()
+            .collect();
+
+        let mut values = BTreeMap::new();
+        // layers are applied from the lowest precedence, each replacing the values below it
+        for layer in layers.split(',').map(str::trim).rev() {
+            match layer {
+                CREDENTIALS_LAYER => {
+                    for (key, value) in credentials.iter() {
+                        let allowed = overrides.as_ref().is_none_or(|o| o.contains(key));
+                        if allowed || !values.contains_key(key) {
+                            values.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+                CONFIG_LAYER => values.extend(config.iter().cloned()),
+                layer => Err(Error::Upstream(format!(
+                    "Config '{}' must list '{}' or '{}', got: {}",
+                    LAYERS_KEY, CREDENTIALS_LAYER, CONFIG_LAYER, layer
+                )))?,
+            }
+        }
+
+        Ok(values.into_iter().collect())
+    }
+
+    fn credentials(binding_id: &str, ttl: u64) -> Result<Vec<(String, String)>, Error> {
+        let cached = CACHE.with_borrow(|cache| {
+            cache
+                .get(binding_id)
+                .filter(|c| c.is_fresh(ttl))
+                .map(|c| c.credentials.clone())
+        });
+        match cached {
+            Some(credentials) => Ok(credentials),
+            None => CredentialConfig::fetch_credentials(binding_id),
+        }
+    }
+}
+
 impl Guest for CredentialConfig {
     fn get(key: String) -> Result<Option<String>, Error> {
         Ok(CredentialConfig::get_all()?.iter().find_map(|(k, v)| {
@@ -20,24 +212,46 @@ impl Guest for CredentialConfig {
     }
 
     fn get_all() -> Result<Vec<(String, String)>, Error> {
-        match store::get(BINDING_ID_KEY).map_err(config_err_map)? {
-            None => Err(Error::Upstream(String::from(format!(
-                "Config must contain '{}'",
-                BINDING_ID_KEY
-            )))),
-            Some(binding_id) => {
-                let credentials = fetch(&binding_id)
-                    .map_err(|e| Error::Upstream(e.to_string()))?
-                    .iter()
-                    .map(|c| (c.key.clone(), c.value.clone()))
-                    .collect();
-
-                Ok(credentials)
-            }
+        // the upstream config is read once for each lookup
+        let config = store::get_all().map_err(config_err_map)?;
+        let ttl = CredentialConfig::cache_ttl(&config)?;
+        let mut credentials = vec![];
+        for (namespace, binding_id) in CredentialConfig::bindings(&config)? {
+            let binding_credentials = CredentialConfig::credentials(&binding_id, ttl)?;
+            credentials.extend(
+                binding_credentials
+                    .into_iter()
+                    .map(|(k, v)| match &namespace {
+                        Some(namespace) => (format!("{namespace}.{k}"), v),
+                        None => (k, v),
+                    }),
+            );
+        }
+        let credentials = KeyMapping::from_config(&config).apply(&credentials)?;
+        CredentialConfig::layer(credentials, config)
+    }
+}
+
+impl CredentialCache for CredentialConfig {
+    fn refresh() -> Result<(), String> {
+        let config = store::get_all()
+            .map_err(config_err_map)
+            .map_err(config_err_string)?;
+        for (_, binding_id) in CredentialConfig::bindings(&config).map_err(config_err_string)? {
+            CredentialConfig::fetch_credentials(&binding_id).map_err(config_err_string)?;
         }
+
+        Ok(())
     }
 }
 
+fn find(config: &[(String, String)], key: &str) -> Option<String> {
+    config
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.clone())
+}
+
 fn config_err_map(err: store::Error) -> Error {
     match err {
         store::Error::Upstream(err) => Error::Upstream(err),
@@ -45,6 +259,13 @@ fn config_err_map(err: store::Error) -> Error {
     }
 }
 
+fn config_err_string(err: Error) -> String {
+    match err {
+        Error::Upstream(err) => err,
+        Error::Io(err) => err,
+    }
+}
+
 wit_bindgen::generate!({
     path: "../wit",
     world: "credential-config",
@@ -0,0 +1,106 @@
+use crate::exports::wasi::config::store::Error;
+use std::collections::BTreeMap;
+
+const STRIP_PREFIX_KEY: &str = "strip-prefix";
+const ADD_PREFIX_KEY: &str = "add-prefix";
+const RENAME_KEY_PREFIX: &str = "rename.";
+const DEFAULT_KEY_PREFIX: &str = "default.";
+const TEMPLATE_KEY_PREFIX: &str = "template.";
+
+/// Mapping from the keys published as credentials to the keys a client expects, read from the
+/// upstream config. Mappings are applied in order:
+///
+/// - `strip-prefix=<prefix>` removes the prefix from keys that start with it
+/// - `rename.<key>=<new-key>` renames a key
+/// - `default.<key>=<value>` sets a value for a key that is not present
+/// - `template.<key>=<template>` sets a value composed from other values, each `{key}` in the
+///   template is replaced with the value of the key
+/// - `add-prefix=<prefix>` adds the prefix to every key
+#[derive(Default)]
+pub(crate) struct KeyMapping {
+    strip_prefix: Option<String>,
+    add_prefix: Option<String>,
+    renames: BTreeMap<String, String>,
+    defaults: BTreeMap<String, String>,
+    templates: BTreeMap<String, String>,
+}
+
+impl KeyMapping {
+    pub(crate) fn from_config(config: &[(String, String)]) -> Self {
+        let mut mapping = KeyMapping::default();
+        for (key, value) in config.iter().cloned() {
+            if key == STRIP_PREFIX_KEY {
+                mapping.strip_prefix = Some(value);
+            } else if key == ADD_PREFIX_KEY {
+                mapping.add_prefix = Some(value);
+            } else if let Some(key) = key.strip_prefix(RENAME_KEY_PREFIX) {
+                mapping.renames.insert(key.to_string(), value);
+            } else if let Some(key) = key.strip_prefix(DEFAULT_KEY_PREFIX) {
+                mapping.defaults.insert(key.to_string(), value);
+            } else if let Some(key) = key.strip_prefix(TEMPLATE_KEY_PREFIX) {
+                mapping.templates.insert(key.to_string(), value);
+            }
+        }
+        mapping
+    }
+
+    /// Whether an upstream config key configures the mapping, rather than configuring a client.
+    pub(crate) fn is_mapping_key(key: &str) -> bool {
+        key == STRIP_PREFIX_KEY
+            || key == ADD_PREFIX_KEY
+            || [RENAME_KEY_PREFIX, DEFAULT_KEY_PREFIX, TEMPLATE_KEY_PREFIX]
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+    }
+
+    pub(crate) fn apply(
+        &self,
+        credentials: &[(String, String)],
+    ) -> Result<Vec<(String, String)>, Error> {
+        let mut values = BTreeMap::new();
+        for (key, value) in credentials {
+            let key = match &self.strip_prefix {
+                Some(prefix) => key.strip_prefix(prefix.as_str()).unwrap_or(key),
+                None => key,
+            };
+            let key = self.renames.get(key).map(String::as_str).unwrap_or(key);
+            values.insert(key.to_string(), value.clone());
+        }
+        for (key, value) in self.defaults.iter() {
+            values.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        // templates are composed from the mapped values, not from other templates
+        let mut composed = vec![];
+        for (key, template) in self.templates.iter() {
+            composed.push((key.clone(), render(key, template, &values)?));
+        }
+        values.extend(composed);
+
+        let prefix = self.add_prefix.as_deref().unwrap_or_default();
+        Ok(values
+            .into_iter()
+            .map(|(key, value)| (format!("{prefix}{key}"), value))
+            .collect())
+    }
+}
+
+fn render(key: &str, template: &str, values: &BTreeMap<String, String>) -> Result<String, Error> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| {
+            Error::Upstream(format!("Template for '{key}' has an unterminated '{{'"))
+        })?;
+        let name = &rest[start + 1..start + end];
+        let value = values.get(name).ok_or_else(|| {
+            Error::Upstream(format!(
+                "Template for '{key}' references missing credential '{name}'"
+            ))
+        })?;
+        rendered.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
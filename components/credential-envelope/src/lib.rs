@@ -0,0 +1,359 @@
+//! Envelope encryption for credentials stored at rest by the credential admins and stores.
+//!
+//! Each record is encrypted with its own data key. The data key is encrypted, or wrapped, with a
+//! key encryption key identified by a key id, which is recorded with the record so keys can be
+//! rotated. Records are encrypted with AES-256-GCM, using the id of the instance or binding as
+//! associated data so a record is only valid for the id it was published for.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+pub type Error = String;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const ENVELOPE_FIELD: &str = "envelope";
+const KEY_ID_FIELD: &str = "key-id";
+const WRAPPED_KEY_FIELD: &str = "wrapped-key";
+const NONCE_FIELD: &str = "nonce";
+const CIPHERTEXT_FIELD: &str = "ciphertext";
+
+/// Key encryption keys, by key id.
+pub struct Keys {
+    keys: BTreeMap<String, [u8; KEY_LEN]>,
+    current: String,
+    allow_plaintext: bool,
+}
+
+impl Keys {
+    /// Parse `<key-id>=<key>` entries separated by commas or new lines, each key is 32 bytes
+    /// encoded as hex. New records are encrypted with the `current` key, or the last key listed.
+    pub fn parse(keys: &str, current: Option<&str>) -> Result<Self, Error> {
+        let mut parsed = BTreeMap::new();
+        let mut last = None;
+        for entry in keys
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            let (key_id, key) = entry
+                .split_once('=')
+                .ok_or_else(|| Error::from("encryption keys must be listed as '<key-id>=<key>'"))?;
+            let key: [u8; KEY_LEN] = decode_hex(key)?.try_into().map_err(|_| {
+                Error::from(format!(
+                    "encryption key '{key_id}' must be {KEY_LEN} bytes encoded as hex"
+                ))
+            })?;
+            parsed.insert(key_id.to_string(), key);
+            last = Some(key_id.to_string());
+        }
+        let current = current
+            .map(String::from)
+            .or(last)
+            .ok_or_else(|| Error::from("at least one encryption key must be listed"))?;
+        if !parsed.contains_key(&current) {
+            Err(Error::from(format!(
+                "encryption key '{current}' is not listed"
+            )))?;
+        }
+        Ok(Keys {
+            keys: parsed,
+            current,
+            allow_plaintext: false,
+        })
+    }
+
+    /// Read records that are not encrypted, such as records stored before keys were configured.
+    /// By default they are refused, so a record replaced with plaintext is not trusted.
+    pub fn allow_plaintext(mut self, allow: bool) -> Self {
+        self.allow_plaintext = allow;
+        self
+    }
+
+    /// Id of the key new records are encrypted with.
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    /// Encrypt a record with a new data key, wrapped with the current key.
+    pub fn seal(
+        &self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+        random: fn(u64) -> Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        let data_key = random(KEY_LEN as u64);
+        let nonce = random(NONCE_LEN as u64);
+        let ciphertext = cipher(&data_key)
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: associated_data,
+                },
+            )
+            .map_err(|_| Error::from("unable to encrypt record"))?;
+
+        let wrapping_nonce = random(NONCE_LEN as u64);
+        let mut wrapped_key = wrapping_nonce.clone();
+        wrapped_key.extend(
+            cipher(&self.keys[&self.current])
+                .encrypt(
+                    Nonce::from_slice(&wrapping_nonce),
+                    Payload {
+                        msg: &data_key,
+                        aad: self.current.as_bytes(),
+                    },
+                )
+                .map_err(|_| Error::from("unable to wrap data key"))?,
+        );
+
+        let record = json!({
+            ENVELOPE_FIELD: {
+                KEY_ID_FIELD: self.current,
+                WRAPPED_KEY_FIELD: encode_hex(&wrapped_key),
+                NONCE_FIELD: encode_hex(&nonce),
+                CIPHERTEXT_FIELD: encode_hex(&ciphertext),
+            }
+        });
+        serde_json::to_vec(&record).map_err(|e| Error::from(e.to_string()))
+    }
+
+    /// Decrypt a record encrypted with any of the keys. Records that are not encrypted are
+    /// returned as is only when plaintext is allowed.
+    pub fn open(&self, record: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, Error> {
+        let Some(envelope) = envelope(record) else {
+            if !self.allow_plaintext {
+                Err(Error::from(
+                    "record is not encrypted, but encryption keys are configured",
+                ))?;
+            }
+            return Ok(record.to_vec());
+        };
+        let field = |name: &str| {
+            envelope[name]
+                .as_str()
+                .ok_or_else(|| Error::from(format!("encrypted record must contain '{name}'")))
+        };
+        let key_id = field(KEY_ID_FIELD)?;
+        let key = self.keys.get(key_id).ok_or_else(|| {
+            Error::from(format!(
+                "record is encrypted with key '{key_id}', which is not listed"
+            ))
+        })?;
+
+        let wrapped_key = decode_hex(field(WRAPPED_KEY_FIELD)?)?;
+        if wrapped_key.len() < NONCE_LEN {
+            Err(Error::from("encrypted record has an invalid wrapped key"))?;
+        }
+        let (wrapping_nonce, wrapped_key) = wrapped_key.split_at(NONCE_LEN);
+        let data_key = cipher(key)
+            .decrypt(
+                Nonce::from_slice(wrapping_nonce),
+                Payload {
+                    msg: wrapped_key,
+                    aad: key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| Error::from(format!("unable to unwrap data key with key '{key_id}'")))?;
+
+        let nonce = decode_hex(field(NONCE_FIELD)?)?;
+        if nonce.len() != NONCE_LEN || data_key.len() != KEY_LEN {
+            Err(Error::from(
+                "encrypted record has an invalid nonce or data key",
+            ))?;
+        }
+        cipher(&data_key)
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &decode_hex(field(CIPHERTEXT_FIELD)?)?,
+                    aad: associated_data,
+                },
+            )
+            .map_err(|_| Error::from("unable to decrypt record"))
+    }
+
+    /// Encrypt a record with the current key, `None` if it already is. Records that are not
+    /// encrypted are encrypted too, whether or not plaintext is allowed, so records stored before
+    /// keys were configured can be migrated.
+    pub fn reencrypt(
+        &self,
+        record: &[u8],
+        associated_data: &[u8],
+        random: fn(u64) -> Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let plaintext = match envelope(record) {
+            None => record.to_vec(),
+            Some(envelope) if envelope[KEY_ID_FIELD].as_str() == Some(&self.current) => {
+                return Ok(None)
+            }
+            Some(_) => self.open(record, associated_data)?,
+        };
+        Ok(Some(self.seal(&plaintext, associated_data, random)?))
+    }
+}
+
+/// Id of the key a record is encrypted with, `None` if the record is not encrypted.
+pub fn key_id(record: &[u8]) -> Option<String> {
+    envelope(record).and_then(|e| e[KEY_ID_FIELD].as_str().map(String::from))
+}
+
+/// Decrypt a record when keys are configured, a record that is encrypted can not be read without
+/// keys.
+pub fn open(keys: Option<&Keys>, record: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, Error> {
+    match keys {
+        Some(keys) => keys.open(record, associated_data),
+        None if envelope(record).is_some() => Err(Error::from(
+            "record is encrypted, but no encryption keys are configured",
+        )),
+        None => Ok(record.to_vec()),
+    }
+}
+
+/// Credentials are stored as an object of string values, an encrypted record holds an object
+/// under the envelope field instead.
+fn envelope(record: &[u8]) -> Option<Value> {
+    match serde_json::from_slice::<Value>(record).ok()? {
+        Value::Object(mut fields) => match fields.remove(ENVELOPE_FIELD)? {
+            envelope @ Value::Object(_) => Some(envelope),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn cipher(key: &[u8]) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        Err(Error::from("value must be encoded as hex"))?;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| Error::from("value must be encoded as hex"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU8, Ordering};
+
+    const KEYS: &str = "k1=0101010101010101010101010101010101010101010101010101010101010101,\
+                        k2=0202020202020202020202020202020202020202020202020202020202020202";
+    const ID: &[u8] = b"instance";
+    const CREDENTIALS: &[u8] = br#"{"password":"secret"}"#;
+
+    /// Distinct bytes for each call, data keys and nonces must not repeat.
+    fn random(len: u64) -> Vec<u8> {
+        static NEXT: AtomicU8 = AtomicU8::new(0);
+        (0..len)
+            .map(|_| NEXT.fetch_add(1, Ordering::Relaxed))
+            .collect()
+    }
+
+    fn keys(current: &str) -> Keys {
+        Keys::parse(KEYS, Some(current)).unwrap()
+    }
+
+    /// Flip the last hex digit of a field of the envelope.
+    fn tamper(record: &[u8], name: &str) -> Vec<u8> {
+        let mut record: Value = serde_json::from_slice(record).unwrap();
+        let field = &mut record[ENVELOPE_FIELD][name];
+        let mut value = field.as_str().unwrap().to_string();
+        let last = match value.pop().unwrap() {
+            '0' => '1',
+            _ => '0',
+        };
+        value.push(last);
+        *field = Value::String(value);
+        serde_json::to_vec(&record).unwrap()
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let keys = keys("k1");
+        let record = keys.seal(CREDENTIALS, ID, random).unwrap();
+        assert!(!String::from_utf8_lossy(&record).contains("secret"));
+        assert_eq!(key_id(&record).as_deref(), Some("k1"));
+        assert_eq!(keys.open(&record, ID).unwrap(), CREDENTIALS);
+        assert_eq!(open(Some(&keys), &record, ID).unwrap(), CREDENTIALS);
+    }
+
+    #[test]
+    fn open_with_wrong_associated_data_is_rejected() {
+        let keys = keys("k1");
+        let record = keys.seal(CREDENTIALS, ID, random).unwrap();
+        assert!(keys.open(&record, b"other-instance").is_err());
+    }
+
+    #[test]
+    fn tampered_records_are_rejected() {
+        let keys = keys("k1");
+        let record = keys.seal(CREDENTIALS, ID, random).unwrap();
+        for name in [CIPHERTEXT_FIELD, WRAPPED_KEY_FIELD, NONCE_FIELD] {
+            assert!(
+                keys.open(&tamper(&record, name), ID).is_err(),
+                "tampered {name} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn records_of_unknown_keys_are_rejected() {
+        let record = keys("k2").seal(CREDENTIALS, ID, random).unwrap();
+        let k1_only = Keys::parse(KEYS.split(',').next().unwrap(), None).unwrap();
+        let e = k1_only.open(&record, ID).unwrap_err();
+        assert!(e.contains("'k2'"), "{e}");
+        assert!(open(None, &record, ID).is_err());
+        assert!(Keys::parse(KEYS, Some("k3")).is_err());
+    }
+
+    #[test]
+    fn previous_keys_open_records() {
+        let record = keys("k1").seal(CREDENTIALS, ID, random).unwrap();
+        assert_eq!(keys("k2").open(&record, ID).unwrap(), CREDENTIALS);
+    }
+
+    #[test]
+    fn plaintext_is_refused_unless_allowed() {
+        assert!(keys("k1").open(CREDENTIALS, ID).is_err());
+        assert_eq!(
+            keys("k1")
+                .allow_plaintext(true)
+                .open(CREDENTIALS, ID)
+                .unwrap(),
+            CREDENTIALS
+        );
+        assert_eq!(open(None, CREDENTIALS, ID).unwrap(), CREDENTIALS);
+    }
+
+    #[test]
+    fn reencrypt_seals_records_not_under_the_current_key() {
+        let k1 = keys("k1");
+        let k2 = keys("k2");
+        let record = k1.seal(CREDENTIALS, ID, random).unwrap();
+        assert!(k1.reencrypt(&record, ID, random).unwrap().is_none());
+
+        let rotated = k2.reencrypt(&record, ID, random).unwrap().unwrap();
+        assert_eq!(key_id(&rotated).as_deref(), Some("k2"));
+        assert_eq!(k2.open(&rotated, ID).unwrap(), CREDENTIALS);
+        assert!(k2.reencrypt(&rotated, ID, random).unwrap().is_none());
+
+        let sealed = k2.reencrypt(CREDENTIALS, ID, random).unwrap().unwrap();
+        assert_eq!(key_id(&sealed).as_deref(), Some("k2"));
+        assert_eq!(k2.open(&sealed, ID).unwrap(), CREDENTIALS);
+    }
+}
@@ -1,44 +1,242 @@
 #![no_main]
 
+use credential_envelope::Keys;
 use exports::componentized::services::credential_admin::{Credential, Error, Guest, ServiceId};
+use exports::componentized::services::credential_encryption::Guest as CredentialEncryption;
+use exports::componentized::services::credential_versions::Guest as CredentialVersions;
 use serde_json;
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use wasi::random::random::get_random_bytes;
 
 const PATH_KEY: &str = "path";
 const PATH_DEFAULT: &str = "services";
+const ENCRYPTION_KEYS_KEY: &str = "encryption-keys";
+const ENCRYPTION_KEY_FILE_KEY: &str = "encryption-key-file";
+const ENCRYPTION_KEY_ID_KEY: &str = "encryption-key-id";
+const ALLOW_PLAINTEXT_KEY: &str = "allow-plaintext";
+const MAX_VERSIONS_KEY: &str = "max-versions";
+const MAX_VERSIONS_DEFAULT: usize = 10;
 
 pub(crate) struct FilesystemCredentialAdmin;
 
 impl FilesystemCredentialAdmin {
-    fn get_path(id: ServiceId) -> Result<PathBuf, Error> {
+    fn get_base_path() -> Result<PathBuf, Error> {
         let base_path = wasi::config::store::get(PATH_KEY)
             .map_err(|e| Error::from(e.to_string()))?
             .unwrap_or(String::from(PATH_DEFAULT));
 
-        Ok(PathBuf::new().join(base_path).join("credentials").join(id))
+        Ok(PathBuf::from(base_path))
+    }
+    fn get_credentials_path() -> Result<PathBuf, Error> {
+        Ok(FilesystemCredentialAdmin::get_base_path()?.join("credentials"))
+    }
+    fn get_path(id: ServiceId) -> Result<PathBuf, Error> {
+        service_ids::validate_id(&id)?;
+        Ok(FilesystemCredentialAdmin::get_credentials_path()?.join(id))
+    }
+    /// Each version of the credentials is kept in a directory named after the id, the latest
+    /// version is also written to the credentials directory for the credential store.
+    fn get_versions_path(id: ServiceId) -> Result<PathBuf, Error> {
+        service_ids::validate_id(&id)?;
+        Ok(FilesystemCredentialAdmin::get_base_path()?
+            .join("credential-versions")
+            .join(id))
+    }
+    fn versions(versions_path: &Path) -> Result<Vec<u64>, Error> {
+        let dir = match fs::read_dir(versions_path) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => Err(Error::from(e.to_string()))?,
+        };
+        let mut versions = vec![];
+        for entry in dir {
+            let entry = entry.map_err(|e| Error::from(e.to_string()))?;
+            if let Some(version) = entry.file_name().to_str().and_then(|v| v.parse().ok()) {
+                versions.push(version);
+            }
+        }
+        versions.sort();
+        Ok(versions)
+    }
+    /// Number of versions kept for each id, older versions are deleted as new ones are published.
+    fn get_max_versions() -> Result<usize, Error> {
+        let Some(max_versions) =
+            wasi::config::store::get(MAX_VERSIONS_KEY).map_err(|e| Error::from(e.to_string()))?
+        else {
+            return Ok(MAX_VERSIONS_DEFAULT);
+        };
+        match max_versions.parse() {
+            Ok(max_versions) if max_versions > 0 => Ok(max_versions),
+            _ => Err(Error::from(format!(
+                "Config '{MAX_VERSIONS_KEY}' must be a positive number, got: {max_versions}"
+            ))),
+        }
+    }
+    /// Replace the contents of a file, readers either see the previous or the new contents. The
+    /// contents are written to a temporary file which is renamed over the file.
+    fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Error> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, contents).map_err(|e| Error::from(e.to_string()))?;
+        fs::rename(&temp_path, path).map_err(|e| Error::from(e.to_string()))
+    }
+    /// Re-encrypt a record with the current key, unless it already is.
+    fn reencrypt_record(keys: &Keys, id: &ServiceId, path: &Path) -> Result<bool, Error> {
+        let record = fs::read(path).map_err(|e| Error::from(e.to_string()))?;
+        let Some(creds) = keys.reencrypt(&record, id.as_bytes(), get_random_bytes)? else {
+            return Ok(false);
+        };
+        FilesystemCredentialAdmin::write_atomically(path, &creds)?;
+        Ok(true)
+    }
+    /// Keys credentials are encrypted with, listed by the `encryption-keys` config value or in
+    /// the file at the `encryption-key-file` path. Credentials are stored as plain JSON when no
+    /// keys are configured. Once keys are configured, credentials stored as plain JSON are only
+    /// read when the `allow-plaintext` config value is `true`.
+    fn get_keys() -> Result<Option<Keys>, Error> {
+        let keys = match wasi::config::store::get(ENCRYPTION_KEYS_KEY)
+            .map_err(|e| Error::from(e.to_string()))?
+        {
+            Some(keys) => keys,
+            None => match wasi::config::store::get(ENCRYPTION_KEY_FILE_KEY)
+                .map_err(|e| Error::from(e.to_string()))?
+            {
+                Some(path) => fs::read_to_string(path).map_err(|e| Error::from(e.to_string()))?,
+                None => return Ok(None),
+            },
+        };
+        let key_id = wasi::config::store::get(ENCRYPTION_KEY_ID_KEY)
+            .map_err(|e| Error::from(e.to_string()))?;
+        let allow_plaintext = wasi::config::store::get(ALLOW_PLAINTEXT_KEY)
+            .map_err(|e| Error::from(e.to_string()))?
+            .is_some_and(|v| v == "true");
+        Ok(Some(
+            Keys::parse(&keys, key_id.as_deref())?.allow_plaintext(allow_plaintext),
+        ))
     }
 }
 
 impl Guest for FilesystemCredentialAdmin {
     fn publish(id: ServiceId, credentials: Vec<Credential>) -> Result<(), Error> {
+        let max_versions = FilesystemCredentialAdmin::get_max_versions()?;
         let mut creds = HashMap::new();
         for Credential { key, value } in credentials {
             creds.insert(key, value);
         }
-        let creds = serde_json::to_string(&creds).map_err(|e| Error::from(e.to_string()))?;
+        let mut creds = serde_json::to_vec(&creds).map_err(|e| Error::from(e.to_string()))?;
+        if let Some(keys) = FilesystemCredentialAdmin::get_keys()? {
+            creds = keys.seal(&creds, id.as_bytes(), get_random_bytes)?;
+        }
 
-        let path = FilesystemCredentialAdmin::get_path(id)?;
+        let path = FilesystemCredentialAdmin::get_path(id.clone())?;
         let mut dir = path.clone();
         dir.pop();
         fs::create_dir_all(dir).map_err(|e| Error::from(e.to_string()))?;
-        fs::write(path, creds).map_err(|e| Error::from(e.to_string()))
+
+        let versions_path = FilesystemCredentialAdmin::get_versions_path(id)?;
+        fs::create_dir_all(&versions_path).map_err(|e| Error::from(e.to_string()))?;
+        let mut versions = FilesystemCredentialAdmin::versions(&versions_path)?;
+        // credentials published before versions were kept become the first version
+        if versions.is_empty() && path.is_file() {
+            let previous = fs::read(&path).map_err(|e| Error::from(e.to_string()))?;
+            FilesystemCredentialAdmin::write_atomically(&versions_path.join("1"), &previous)?;
+            versions.push(1);
+        }
+        let version = versions.last().map_or(1, |v| v + 1);
+        FilesystemCredentialAdmin::write_atomically(
+            &versions_path.join(version.to_string()),
+            &creds,
+        )?;
+        FilesystemCredentialAdmin::write_atomically(&path, &creds)?;
+        versions.push(version);
+        // the credentials are published, versions failing to be pruned are pruned by the next
+        // publish
+        for version in &versions[..versions.len().saturating_sub(max_versions)] {
+            let _ = fs::remove_file(versions_path.join(version.to_string()));
+        }
+        Ok(())
     }
 
     fn destroy(id: ServiceId) -> Result<(), Error> {
-        fs::remove_file(FilesystemCredentialAdmin::get_path(id)?)
-            .map_err(|e| Error::from(e.to_string()))
+        match fs::remove_dir_all(FilesystemCredentialAdmin::get_versions_path(id.clone())?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::from(e.to_string()))?,
+            _ => {}
+        }
+        // credentials already destroyed by an earlier, interrupted, call are not an error
+        match fs::remove_file(FilesystemCredentialAdmin::get_path(id)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::from(e.to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl CredentialEncryption for FilesystemCredentialAdmin {
+    fn reencrypt() -> Result<Vec<ServiceId>, Error> {
+        let keys = FilesystemCredentialAdmin::get_keys()?
+            .ok_or_else(|| Error::from("no encryption keys are configured"))?;
+
+        let dir = match fs::read_dir(FilesystemCredentialAdmin::get_credentials_path()?) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => Err(Error::from(e.to_string()))?,
+        };
+        let mut ids = vec![];
+        for entry in dir {
+            let entry = entry.map_err(|e| Error::from(e.to_string()))?;
+            let Some(id) = entry.file_name().to_str().map(ServiceId::from) else {
+                continue;
+            };
+            if service_ids::validate_id(&id).is_err() {
+                continue;
+            }
+            let mut reencrypted =
+                FilesystemCredentialAdmin::reencrypt_record(&keys, &id, &entry.path())?;
+            // previous versions are re-encrypted too, so previous keys can be retired
+            let versions_path = FilesystemCredentialAdmin::get_versions_path(id.clone())?;
+            for version in FilesystemCredentialAdmin::versions(&versions_path)? {
+                reencrypted |= FilesystemCredentialAdmin::reencrypt_record(
+                    &keys,
+                    &id,
+                    &versions_path.join(version.to_string()),
+                )?;
+            }
+            if reencrypted {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+}
+
+impl CredentialVersions for FilesystemCredentialAdmin {
+    fn list_versions(id: ServiceId) -> Result<Vec<u64>, Error> {
+        FilesystemCredentialAdmin::versions(&FilesystemCredentialAdmin::get_versions_path(id)?)
+    }
+
+    fn fetch_version(id: ServiceId, version: u64) -> Result<Vec<Credential>, Error> {
+        let path =
+            FilesystemCredentialAdmin::get_versions_path(id.clone())?.join(version.to_string());
+        let record = match fs::read(path) {
+            Ok(record) => record,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::from(format!(
+                "version {version} of credentials for '{id}' not found"
+            )))?,
+            Err(e) => Err(Error::from(e.to_string()))?,
+        };
+        let record = credential_envelope::open(
+            FilesystemCredentialAdmin::get_keys()?.as_ref(),
+            &record,
+            id.as_bytes(),
+        )?;
+        let creds: HashMap<String, String> =
+            serde_json::from_slice(&record).map_err(|e| Error::from(e.to_string()))?;
+        Ok(creds
+            .into_iter()
+            .map(|(key, value)| Credential { key, value })
+            .collect())
     }
 }
 
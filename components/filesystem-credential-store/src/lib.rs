@@ -1,5 +1,6 @@
 #![no_main]
 
+use credential_envelope::Keys;
 use exports::componentized::services::credential_store::{Credential, Error, Guest, ServiceId};
 use serde_json;
 use std::collections::HashMap;
@@ -8,23 +9,58 @@ use std::path::PathBuf;
 
 const PATH_KEY: &str = "path";
 const PATH_DEFAULT: &str = "services";
+const ENCRYPTION_KEYS_KEY: &str = "encryption-keys";
+const ENCRYPTION_KEY_FILE_KEY: &str = "encryption-key-file";
+const ALLOW_PLAINTEXT_KEY: &str = "allow-plaintext";
+const LIST_PAGE_SIZE: usize = 100;
 
 pub(crate) struct FilesystemCredentialStore;
 
 impl FilesystemCredentialStore {
-    fn get_credential_path(id: ServiceId) -> Result<PathBuf, Error> {
+    fn get_credentials_path() -> Result<PathBuf, Error> {
         let base_path = wasi::config::store::get(PATH_KEY)
             .map_err(|e| Error::from(e.to_string()))?
             .unwrap_or(String::from(PATH_DEFAULT));
 
-        Ok(PathBuf::new().join(base_path).join("credentials").join(id))
+        Ok(PathBuf::new().join(base_path).join("credentials"))
+    }
+    fn get_credential_path(id: ServiceId) -> Result<PathBuf, Error> {
+        service_ids::validate_id(&id)?;
+        Ok(FilesystemCredentialStore::get_credentials_path()?.join(id))
+    }
+    /// Keys credentials may be encrypted with, listed by the `encryption-keys` config value or in
+    /// the file at the `encryption-key-file` path. Once keys are configured, credentials stored as
+    /// plain JSON are only read when the `allow-plaintext` config value is `true`.
+    fn get_keys() -> Result<Option<Keys>, Error> {
+        let keys = match wasi::config::store::get(ENCRYPTION_KEYS_KEY)
+            .map_err(|e| Error::from(e.to_string()))?
+        {
+            Some(keys) => keys,
+            None => match wasi::config::store::get(ENCRYPTION_KEY_FILE_KEY)
+                .map_err(|e| Error::from(e.to_string()))?
+            {
+                Some(path) => fs::read_to_string(path).map_err(|e| Error::from(e.to_string()))?,
+                None => return Ok(None),
+            },
+        };
+        let allow_plaintext = wasi::config::store::get(ALLOW_PLAINTEXT_KEY)
+            .map_err(|e| Error::from(e.to_string()))?
+            .is_some_and(|v| v == "true");
+        Ok(Some(
+            Keys::parse(&keys, None)?.allow_plaintext(allow_plaintext),
+        ))
     }
 }
 
 impl Guest for FilesystemCredentialStore {
     fn fetch(id: ServiceId) -> Result<Vec<Credential>, Error> {
-        let bytes = fs::read(FilesystemCredentialStore::get_credential_path(id)?)
+        let bytes = fs::read(FilesystemCredentialStore::get_credential_path(id.clone())?)
             .map_err(|e| Error::from(e.to_string()))?;
+        let bytes = credential_envelope::open(
+            FilesystemCredentialStore::get_keys()?.as_ref(),
+            &bytes,
+            id.as_bytes(),
+        )?;
         let creds: HashMap<String, String> =
             serde_json::from_slice(bytes.as_slice()).map_err(|e| Error::from(e.to_string()))?;
         let creds = creds
@@ -36,6 +72,37 @@ impl Guest for FilesystemCredentialStore {
             .collect();
         Ok(creds)
     }
+
+    /// Ids are listed in order, the cursor is the last id of the previous page.
+    fn list(cursor: Option<String>) -> Result<(Vec<ServiceId>, Option<String>), Error> {
+        let dir = match fs::read_dir(FilesystemCredentialStore::get_credentials_path()?) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((vec![], None)),
+            Err(e) => Err(Error::from(e.to_string()))?,
+        };
+        let mut ids = vec![];
+        for entry in dir {
+            let entry = entry.map_err(|e| Error::from(e.to_string()))?;
+            let Some(id) = entry.file_name().to_str().map(ServiceId::from) else {
+                continue;
+            };
+            if service_ids::validate_id(&id).is_err()
+                || !entry.path().is_file()
+                || cursor.as_ref().is_some_and(|cursor| id <= *cursor)
+            {
+                continue;
+            }
+            ids.push(id);
+        }
+        ids.sort();
+
+        if ids.len() <= LIST_PAGE_SIZE {
+            return Ok((ids, None));
+        }
+        ids.truncate(LIST_PAGE_SIZE);
+        let next_cursor = ids.last().cloned();
+        Ok((ids, next_cursor))
+    }
 }
 
 wit_bindgen::generate!({
@@ -0,0 +1,649 @@
+use crate::componentized::services::types::{Request, Tier};
+use crate::exports::componentized::services::archive::{
+    ArchiveReader, ArchiveWriter, Guest as Archive, GuestArchiveReader, GuestArchiveWriter,
+};
+use crate::exports::componentized::services::lifecycle::{
+    Error, Guest as Lifecycle, ServiceInstanceId,
+};
+use crate::metadata::InstanceMetadata;
+use crate::{FilesystemService, TEMPLATE_REQUEST};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const MANIFEST_PATH: &str = "manifest.json";
+const DATA_PATH: &str = "data";
+const BLOCK_SIZE: u64 = 512;
+/// The manifest is held in memory while it is imported.
+const MAX_MANIFEST_SIZE: u64 = 16 * 1024 * 1024;
+/// Size of the reads hashing the files of an instance.
+const HASH_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A directory, or a file and its size and checksum, within the data of an instance.
+struct DataEntry {
+    /// Path relative to the data directory, separated by `/`.
+    path: String,
+    /// Size and SHA-256 checksum of a file, `None` for a directory.
+    file: Option<(u64, String)>,
+}
+
+/// Archives are tar files. The first entry is `manifest.json`, describing the instance and the
+/// size and SHA-256 checksum of each file. The data directory of the instance follows, under
+/// `data/`. Archives are streamed, only the manifest is held in memory.
+impl Archive for FilesystemService {
+    type ArchiveReader = FilesystemArchiveReader;
+    type ArchiveWriter = FilesystemArchiveWriter;
+
+    fn export_archive(instance_id: ServiceInstanceId) -> Result<ArchiveReader, Error> {
+        FilesystemService::require_instance(instance_id.clone())?;
+
+        let instance = InstanceMetadata::load(&FilesystemService::get_instance_metadata_path(
+            instance_id.clone(),
+        )?)?;
+        let data_path = FilesystemService::get_data_path(instance_id)?;
+        let mut entries = vec![];
+        read_entries(&data_path, "", &mut entries)?;
+
+        let directories: Vec<&str> = entries
+            .iter()
+            .filter(|e| e.file.is_none())
+            .map(|e| e.path.as_str())
+            .collect();
+        let files: Vec<Value> = entries
+            .iter()
+            .filter_map(|e| {
+                e.file.as_ref().map(|(size, sha256)| {
+                    json!({
+                        "path": e.path,
+                        "size": size,
+                        "sha256": sha256,
+                    })
+                })
+            })
+            .collect();
+        let manifest = json!({
+            "type": "filesystem",
+            "tier": instance.as_ref().and_then(|i| i.tier.clone()),
+            "requests": instance.map(|i| i.requests).unwrap_or_default(),
+            "directories": directories,
+            "files": files,
+        })
+        .to_string();
+
+        let mut builder = tar::Builder::new(vec![]);
+        append_header(
+            &mut builder,
+            MANIFEST_PATH,
+            Some(manifest.len() as u64),
+            manifest.as_bytes(),
+        )?;
+        Ok(ArchiveReader::new(FilesystemArchiveReader {
+            export: RefCell::new(Export {
+                data_path,
+                builder,
+                entries: entries.into_iter(),
+                file: None,
+                finished: false,
+            }),
+        }))
+    }
+
+    fn import_archive(instance_id: ServiceInstanceId) -> Result<ArchiveWriter, Error> {
+        // the instance is provisioned once the manifest is read, only an instance provisioned by
+        // this import is destroyed if the import fails
+        FilesystemService::require_new_instance(instance_id.clone())?;
+        Ok(ArchiveWriter::new(FilesystemArchiveWriter {
+            instance_id,
+            import: RefCell::new(Import {
+                block: Vec::with_capacity(BLOCK_SIZE as usize),
+                entry: ImportEntry::Header,
+                long_name: None,
+                checksums: None,
+                restored: BTreeSet::new(),
+                provisioned: false,
+                status: ImportStatus::Importing,
+            }),
+        }))
+    }
+}
+
+pub struct FilesystemArchiveReader {
+    export: RefCell<Export>,
+}
+
+struct Export {
+    data_path: PathBuf,
+    /// Headers, padding and the manifest not yet read, drained from the front.
+    builder: tar::Builder<Vec<u8>>,
+    entries: std::vec::IntoIter<DataEntry>,
+    file: Option<ExportFile>,
+    finished: bool,
+}
+
+/// A file being read into the archive. The file is hashed again as it is read, a file changed since
+/// the manifest was written fails the export.
+struct ExportFile {
+    path: String,
+    file: fs::File,
+    size: u64,
+    remaining: u64,
+    sha256: String,
+    hasher: Sha256,
+}
+
+impl GuestArchiveReader for FilesystemArchiveReader {
+    fn read(&self, len: u64) -> Result<Vec<u8>, Error> {
+        let mut export = self.export.borrow_mut();
+        let export = &mut *export;
+        let len = usize::try_from(len).unwrap_or(usize::MAX);
+        let mut chunk = vec![];
+        while chunk.len() < len {
+            let pending = export.builder.get_mut();
+            if !pending.is_empty() {
+                let n = pending.len().min(len - chunk.len());
+                chunk.extend(pending.drain(..n));
+            } else if let Some(file) = &mut export.file {
+                if file.remaining == 0 {
+                    if format!("{:x}", file.hasher.clone().finalize()) != file.sha256 {
+                        Err(Error::from(format!(
+                            "'{}' changed while it was exported",
+                            file.path
+                        )))?;
+                    }
+                    pending.resize(padding(file.size) as usize, 0);
+                    export.file = None;
+                    continue;
+                }
+                let mut buf = vec![0; (len - chunk.len()).min(file.remaining as usize)];
+                let n = file
+                    .file
+                    .read(&mut buf)
+                    .map_err(|e| Error::from(e.to_string()))?;
+                if n == 0 {
+                    Err(Error::from(format!(
+                        "'{}' changed while it was exported",
+                        file.path
+                    )))?;
+                }
+                file.hasher.update(&buf[..n]);
+                file.remaining -= n as u64;
+                chunk.extend(&buf[..n]);
+            } else if let Some(entry) = export.entries.next() {
+                let path = format!("{DATA_PATH}/{}", entry.path);
+                let Some((size, sha256)) = entry.file else {
+                    append_header(&mut export.builder, &path, None, &[])?;
+                    continue;
+                };
+                let file = fs::File::open(export.data_path.join(&entry.path))
+                    .map_err(|e| Error::from(e.to_string()))?;
+                append_header(&mut export.builder, &path, Some(size), &[])?;
+                export.file = Some(ExportFile {
+                    path: entry.path,
+                    file,
+                    size,
+                    remaining: size,
+                    sha256,
+                    hasher: Sha256::new(),
+                });
+            } else if !export.finished {
+                export
+                    .builder
+                    .finish()
+                    .map_err(|e| Error::from(e.to_string()))?;
+                export.finished = true;
+            } else {
+                break;
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+pub struct FilesystemArchiveWriter {
+    instance_id: ServiceInstanceId,
+    import: RefCell<Import>,
+}
+
+struct Import {
+    /// Bytes of a header block not yet complete.
+    block: Vec<u8>,
+    entry: ImportEntry,
+    /// Path of the next entry, from a GNU long name entry.
+    long_name: Option<String>,
+    /// SHA-256 checksum of each file listed by the manifest, `None` until the manifest is read.
+    checksums: Option<BTreeMap<String, String>>,
+    /// Files restored so far.
+    restored: BTreeSet<String>,
+    provisioned: bool,
+    status: ImportStatus,
+}
+
+enum ImportEntry {
+    Header,
+    Manifest {
+        contents: Vec<u8>,
+        remaining: u64,
+    },
+    LongName {
+        name: Vec<u8>,
+        remaining: u64,
+    },
+    File {
+        path: String,
+        file: fs::File,
+        hasher: Sha256,
+        size: u64,
+        remaining: u64,
+    },
+    Padding {
+        remaining: u64,
+    },
+    /// The end of archive marker was read.
+    End,
+}
+
+impl Import {
+    /// A partially imported instance is not usable.
+    fn abort(&mut self, instance_id: &ServiceInstanceId) {
+        // release the file being restored before its directory is removed
+        self.entry = ImportEntry::End;
+        if self.provisioned {
+            let _ = FilesystemService::destroy(instance_id.clone(), Some(false));
+            self.provisioned = false;
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum ImportStatus {
+    Importing,
+    Failed,
+    Finished,
+}
+
+impl GuestArchiveWriter for FilesystemArchiveWriter {
+    fn write(&self, chunk: Vec<u8>) -> Result<(), Error> {
+        self.attempt(|writer, import| writer.write_chunk(import, &chunk))
+    }
+
+    fn finish(&self) -> Result<(), Error> {
+        self.attempt(|_, import| {
+            // the end of archive marker is optional
+            let complete = match import.entry {
+                ImportEntry::End => true,
+                ImportEntry::Header => import.block.is_empty(),
+                _ => false,
+            };
+            if !complete {
+                Err(Error::from("archive is incomplete"))?;
+            }
+            let Some(checksums) = &import.checksums else {
+                Err(Error::from(format!("archive must contain {MANIFEST_PATH}")))?
+            };
+            if import.restored.len() != checksums.len() {
+                Err(Error::from(
+                    "archive is missing files listed in the manifest",
+                ))?;
+            }
+            import.status = ImportStatus::Finished;
+            Ok(())
+        })
+    }
+}
+
+impl FilesystemArchiveWriter {
+    /// Apply a step of the import, a failed step destroys the partially imported instance and
+    /// fails the import.
+    fn attempt(
+        &self,
+        step: impl FnOnce(&Self, &mut Import) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut import = self.import.borrow_mut();
+        match import.status {
+            ImportStatus::Importing => {}
+            ImportStatus::Failed => Err(Error::from(format!(
+                "import of '{}' failed",
+                self.instance_id
+            )))?,
+            ImportStatus::Finished => Err(Error::from(format!(
+                "import of '{}' is finished",
+                self.instance_id
+            )))?,
+        }
+        if let Err(e) = step(self, &mut import) {
+            import.status = ImportStatus::Failed;
+            import.abort(&self.instance_id);
+            Err(e)?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&self, import: &mut Import, mut chunk: &[u8]) -> Result<(), Error> {
+        while !chunk.is_empty() {
+            match &mut import.entry {
+                ImportEntry::Header => {
+                    let n = (BLOCK_SIZE as usize - import.block.len()).min(chunk.len());
+                    import.block.extend(&chunk[..n]);
+                    chunk = &chunk[n..];
+                    if import.block.len() == BLOCK_SIZE as usize {
+                        let block = std::mem::take(&mut import.block);
+                        import.entry = self.read_header(import, &block)?;
+                    }
+                }
+                ImportEntry::Manifest {
+                    contents,
+                    remaining,
+                } => {
+                    let n = take(&mut chunk, remaining);
+                    contents.extend(n);
+                    if *remaining == 0 {
+                        let contents = std::mem::take(contents);
+                        self.restore_manifest(import, &contents)?;
+                        import.entry = ImportEntry::Padding {
+                            remaining: padding(contents.len() as u64),
+                        };
+                    }
+                }
+                ImportEntry::LongName { name, remaining } => {
+                    let n = take(&mut chunk, remaining);
+                    name.extend(n);
+                    if *remaining == 0 {
+                        let size = name.len() as u64;
+                        let name = String::from_utf8(std::mem::take(name))
+                            .map_err(|_| Error::from("archive entry paths must be UTF-8"))?;
+                        import.long_name = Some(name.trim_end_matches('\0').to_string());
+                        import.entry = ImportEntry::Padding {
+                            remaining: padding(size),
+                        };
+                    }
+                }
+                ImportEntry::File {
+                    path,
+                    file,
+                    hasher,
+                    size,
+                    remaining,
+                } => {
+                    let n = take(&mut chunk, remaining);
+                    hasher.update(n);
+                    file.write_all(n).map_err(|e| Error::from(e.to_string()))?;
+                    if *remaining == 0 {
+                        let sha256 = format!("{:x}", hasher.clone().finalize());
+                        let checksums = import.checksums.as_ref().expect("manifest is read");
+                        if checksums.get(path.as_str()) != Some(&sha256) {
+                            Err(Error::from(format!(
+                                "checksum of '{path}' does not match the manifest"
+                            )))?;
+                        }
+                        let size = *size;
+                        import.restored.insert(std::mem::take(path));
+                        import.entry = ImportEntry::Padding {
+                            remaining: padding(size),
+                        };
+                    }
+                }
+                ImportEntry::Padding { remaining } => {
+                    take(&mut chunk, remaining);
+                    if *remaining == 0 {
+                        import.entry = ImportEntry::Header;
+                    }
+                }
+                ImportEntry::End => {
+                    if chunk.iter().any(|b| *b != 0) {
+                        Err(Error::from("archive continues past its end"))?;
+                    }
+                    chunk = &[];
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the entry described by a header block. The manifest must be the first entry, each
+    /// following entry is a directory or file within the data directory.
+    fn read_header(&self, import: &mut Import, block: &[u8]) -> Result<ImportEntry, Error> {
+        // the end of archive is marked by empty blocks
+        if block.iter().all(|b| *b == 0) {
+            return Ok(ImportEntry::End);
+        }
+        let header = tar::Header::from_byte_slice(block);
+        let checksum: u32 = block
+            .iter()
+            .enumerate()
+            .map(|(i, b)| match i {
+                148..=155 => u32::from(b' '),
+                _ => u32::from(*b),
+            })
+            .sum();
+        if header.cksum().ok() != Some(checksum) {
+            Err(Error::from("archive header checksum does not match"))?;
+        }
+        let size = header.size().map_err(|e| Error::from(e.to_string()))?;
+        let entry_type = header.entry_type();
+        if entry_type == tar::EntryType::GNULongName {
+            return Ok(ImportEntry::LongName {
+                name: vec![],
+                remaining: size,
+            });
+        }
+        let path = match import.long_name.take() {
+            Some(path) => path,
+            None => String::from_utf8(header.path_bytes().into_owned())
+                .map_err(|_| Error::from("archive entry paths must be UTF-8"))?,
+        };
+
+        let Some(checksums) = &import.checksums else {
+            if path != MANIFEST_PATH || entry_type != tar::EntryType::Regular {
+                Err(Error::from(format!(
+                    "archive must start with {MANIFEST_PATH}"
+                )))?;
+            }
+            if size > MAX_MANIFEST_SIZE {
+                Err(Error::from(format!(
+                    "{MANIFEST_PATH} must not exceed {MAX_MANIFEST_SIZE} bytes"
+                )))?;
+            }
+            return Ok(ImportEntry::Manifest {
+                contents: vec![],
+                remaining: size,
+            });
+        };
+
+        let entry_path = Path::new(&path)
+            .strip_prefix(DATA_PATH)
+            .ok()
+            .and_then(|p| p.to_str())
+            .ok_or_else(|| {
+                Error::from(format!(
+                    "archive entry '{path}' must be within the {DATA_PATH} directory"
+                ))
+            })?
+            .trim_end_matches('/')
+            .to_string();
+        let data_path = FilesystemService::get_data_path(self.instance_id.clone())?
+            .join(FilesystemService::validate_sub_path(&entry_path)?);
+        match entry_type {
+            tar::EntryType::Directory => {
+                fs::create_dir_all(&data_path).map_err(|e| Error::from(e.to_string()))?;
+                Ok(ImportEntry::Padding {
+                    remaining: size + padding(size),
+                })
+            }
+            tar::EntryType::Regular => {
+                if !checksums.contains_key(&entry_path) || import.restored.contains(&entry_path) {
+                    Err(Error::from(format!(
+                        "archive entry '{entry_path}' must be listed once by the manifest"
+                    )))?;
+                }
+                if let Some(parent) = data_path.parent() {
+                    fs::create_dir_all(parent).map_err(|e| Error::from(e.to_string()))?;
+                }
+                let file =
+                    fs::File::create_new(&data_path).map_err(|e| Error::from(e.to_string()))?;
+                Ok(ImportEntry::File {
+                    path: entry_path,
+                    file,
+                    hasher: Sha256::new(),
+                    size,
+                    remaining: size,
+                })
+            }
+            _ => Err(Error::from(format!(
+                "archive entry '{entry_path}' must be a file or directory"
+            ))),
+        }
+    }
+
+    /// Provision the instance described by the manifest.
+    fn restore_manifest(&self, import: &mut Import, contents: &[u8]) -> Result<(), Error> {
+        let manifest: Value =
+            serde_json::from_slice(contents).map_err(|e| Error::from(e.to_string()))?;
+        if manifest["type"].as_str() != Some("filesystem") {
+            Err(Error::from("only 'filesystem' archives are supported"))?;
+        }
+        let mut checksums = BTreeMap::new();
+        for file in manifest["files"].as_array().into_iter().flatten() {
+            if let (Some(path), Some(sha256)) = (file["path"].as_str(), file["sha256"].as_str()) {
+                checksums.insert(path.to_string(), sha256.to_string());
+            }
+        }
+
+        // the template was applied when the exported instance was provisioned
+        let tier = manifest["tier"].as_str().map(Tier::from);
+        let requests: Vec<Request> = manifest["requests"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| *key != TEMPLATE_REQUEST)
+            .filter_map(|(key, value)| {
+                value.as_str().map(|value| Request {
+                    key: key.clone(),
+                    value: value.to_string(),
+                })
+            })
+            .collect();
+        // provisioning refuses an instance provisioned with the id since the import started, and
+        // removes what it created when it fails
+        FilesystemService::provision(
+            self.instance_id.clone(),
+            String::from("filesystem"),
+            tier,
+            Some(requests),
+        )?;
+        import.provisioned = true;
+        import.checksums = Some(checksums);
+        Ok(())
+    }
+}
+
+impl Drop for FilesystemArchiveWriter {
+    /// An import abandoned before it is finished destroys the partially imported instance.
+    fn drop(&mut self) {
+        let import = self.import.get_mut();
+        if import.status != ImportStatus::Finished {
+            import.abort(&self.instance_id);
+        }
+    }
+}
+
+/// Read the entries of a directory tree, directories before their contents, hashing each file.
+/// Links are refused, they may lead outside of the instance.
+fn read_entries(dir: &Path, prefix: &str, entries: &mut Vec<DataEntry>) -> Result<(), Error> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).map_err(|e| Error::from(e.to_string()))? {
+        let entry = entry.map_err(|e| Error::from(e.to_string()))?;
+        names.push(entry.file_name());
+    }
+    names.sort();
+    for name in names {
+        let path = dir.join(&name);
+        let name = name
+            .to_str()
+            .ok_or_else(|| Error::from(format!("'{}' must be UTF-8", path.display())))?;
+        let entry_path = match prefix {
+            "" => name.to_string(),
+            prefix => format!("{prefix}/{name}"),
+        };
+        let metadata = fs::symlink_metadata(&path).map_err(|e| Error::from(e.to_string()))?;
+        if metadata.is_dir() {
+            entries.push(DataEntry {
+                path: entry_path.clone(),
+                file: None,
+            });
+            read_entries(&path, &entry_path, entries)?;
+        } else if metadata.is_file() {
+            entries.push(DataEntry {
+                path: entry_path,
+                file: Some(checksum(&path)?),
+            });
+        } else {
+            Err(Error::from(format!(
+                "'{entry_path}' must be a file or directory"
+            )))?;
+        }
+    }
+    Ok(())
+}
+
+/// Size and SHA-256 checksum of a file, read in chunks.
+fn checksum(path: &Path) -> Result<(u64, String), Error> {
+    let mut file = fs::File::open(path).map_err(|e| Error::from(e.to_string()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; HASH_CHUNK_SIZE];
+    let mut size = 0;
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|e| Error::from(e.to_string()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+/// Append the header of an entry, `size` is `None` for a directory. The contents of a file are
+/// appended as the archive is read, only the manifest is appended with its header.
+fn append_header(
+    builder: &mut tar::Builder<Vec<u8>>,
+    path: &str,
+    size: Option<u64>,
+    contents: &[u8],
+) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    match size {
+        Some(size) => {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(size);
+            header.set_mode(0o644);
+        }
+        None => {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            header.set_mode(0o755);
+        }
+    }
+    builder
+        .append_data(&mut header, path, contents)
+        .map_err(|e| Error::from(e.to_string()))
+}
+
+/// Take up to `remaining` bytes from the front of a chunk.
+fn take<'a>(chunk: &mut &'a [u8], remaining: &mut u64) -> &'a [u8] {
+    let n = (*remaining).min(chunk.len() as u64) as usize;
+    let (taken, rest) = chunk.split_at(n);
+    *chunk = rest;
+    *remaining -= n as u64;
+    taken
+}
+
+/// Zeros padding an entry of `size` bytes to a whole block.
+fn padding(size: u64) -> u64 {
+    (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE
+}
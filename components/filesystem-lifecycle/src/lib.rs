@@ -1,26 +1,152 @@
 #![no_main]
 
-use chrono::DateTime;
 use componentized::services::credential_admin::{destroy, publish};
 use componentized::services::types::{Credential, Request, Scope, Tier};
+use exports::componentized::services::catalog::{Guest as Catalog, Offering, RequestOption};
 use exports::componentized::services::lifecycle::{
     Error, Guest as Lifecycle, ServiceBindingId, ServiceInstanceId,
 };
+use exports::componentized::services::quotas::{Guest as Quotas, QuotaUsage};
+use metadata::{BindingMetadata, InstanceMetadata};
+use serde_json::json;
 use std::fs;
-use std::path::PathBuf;
-use wasi::clocks::wall_clock::now;
+use std::path::{Component, Path, PathBuf};
+
+mod archive;
+mod metadata;
 
 const PATH_KEY: &str = "path";
 const PATH_DEFAULT: &str = "services";
 const INSTANCES_PATH_COMPONENT: &str = "instances";
+const TEMPLATES_PATH_KEY: &str = "templates-path";
+const TEMPLATES_PATH_COMPONENT: &str = "templates";
 const BINDINGS_PATH_COMPONENT: &str = "bindings";
 const DATA_PATH_COMPONENT: &str = "data";
+/// Link to the data directory targeted by read-only bindings, distinguishing them from read-write
+/// bindings which target the data directory directly.
+const READ_ONLY_PATH_COMPONENT: &str = "read-only";
+const INSTANCE_METADATA_PATH_COMPONENT: &str = "instance.json";
+const QUOTAS_PATH_COMPONENT: &str = "quotas.json";
+const RESTRICT_KEY: &str = "restrict-over-quota";
+const MAX_FILES_REQUEST: &str = "max-files";
+const MAX_BYTES_REQUEST: &str = "max-bytes";
+/// Request seeding the data of a new instance from a template directory.
+const TEMPLATE_REQUEST: &str = "template";
+const RESTRICTED_FIELD: &str = "restricted";
+const READ_SCOPE: &str = "read";
+const WRITE_SCOPE: &str = "write";
+/// Scope rooting a binding at a sub-path of the instance's data directory, e.g. `path=reports`.
+const PATH_SCOPE_PREFIX: &str = "path=";
+
+/// Quota limits for a tier. Requests may lower, but not raise, the limits of the tier.
+struct TierQuotas {
+    tier: &'static str,
+    max_files: u64,
+    max_bytes: u64,
+}
+
+const TIER_QUOTAS: &[TierQuotas] = &[
+    TierQuotas {
+        tier: "small",
+        max_files: 1_000,
+        max_bytes: 100 * 1024 * 1024,
+    },
+    TierQuotas {
+        tier: "medium",
+        max_files: 10_000,
+        max_bytes: 1024 * 1024 * 1024,
+    },
+    TierQuotas {
+        tier: "large",
+        max_files: 100_000,
+        max_bytes: 10 * 1024 * 1024 * 1024,
+    },
+];
+
+/// Limits derived from the tier and requests of an instance, which are recorded by its metadata.
+#[derive(Debug, Clone, Default)]
+struct InstanceQuotas {
+    max_files: Option<u64>,
+    max_bytes: Option<u64>,
+    /// Bindings are published as read-only while the instance is over quota.
+    restricted: bool,
+}
+
+impl InstanceQuotas {
+    fn from_tier(tier: Option<Tier>, requests: Option<Vec<Request>>) -> Result<Self, Error> {
+        let mut quotas = match &tier {
+            None => InstanceQuotas::default(),
+            Some(tier) => match TIER_QUOTAS.iter().find(|t| t.tier == tier) {
+                Some(t) => InstanceQuotas {
+                    max_files: Some(t.max_files),
+                    max_bytes: Some(t.max_bytes),
+                    ..Default::default()
+                },
+                None => {
+                    let tiers: Vec<&str> = TIER_QUOTAS.iter().map(|t| t.tier).collect();
+                    Err(Error::from(format!(
+                        "a tier must be one of: {}",
+                        tiers.join(", ")
+                    )))?
+                }
+            },
+        };
+
+        for Request { key, value } in requests.unwrap_or_default() {
+            let limit = match key.as_str() {
+                MAX_FILES_REQUEST => &mut quotas.max_files,
+                MAX_BYTES_REQUEST => &mut quotas.max_bytes,
+                _ => Err(Error::from(format!("request '{key}' is not supported")))?,
+            };
+            let value: u64 = value
+                .parse()
+                .map_err(|_| Error::from(format!("request '{key}' must be an integer")))?;
+            if let Some(max) = *limit {
+                if value > max {
+                    Err(Error::from(format!(
+                        "request '{key}' must not exceed {max} for the tier"
+                    )))?;
+                }
+            }
+            *limit = Some(value);
+        }
+
+        Ok(quotas)
+    }
+
+    fn load(path: &Path) -> Result<Self, Error> {
+        // instances provisioned before quotas were recorded are unlimited
+        let Some(quotas) = metadata::read_object(path)? else {
+            return Ok(Self::default());
+        };
+        Ok(InstanceQuotas {
+            max_files: quotas[MAX_FILES_REQUEST].as_u64(),
+            max_bytes: quotas[MAX_BYTES_REQUEST].as_u64(),
+            restricted: quotas[RESTRICTED_FIELD].as_bool().unwrap_or(false),
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        let quotas = json!({
+            MAX_FILES_REQUEST: self.max_files,
+            MAX_BYTES_REQUEST: self.max_bytes,
+            RESTRICTED_FIELD: self.restricted,
+        });
+        metadata::write_atomically(path, &quotas.to_string())
+    }
+
+    fn is_exceeded(&self, files: u64, bytes: u64) -> bool {
+        self.max_files.is_some_and(|max| files > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
 
 #[derive(Debug, Clone)]
 struct FilesystemService {}
 
 impl FilesystemService {
     fn get_instance_path(instance_id: ServiceInstanceId) -> Result<PathBuf, Error> {
+        service_ids::validate_id(&instance_id)?;
         let base_path = wasi::config::store::get(PATH_KEY)
             .map_err(|e| Error::from(e.to_string()))?
             .unwrap_or(String::from(PATH_DEFAULT));
@@ -30,9 +156,35 @@ impl FilesystemService {
             .join(INSTANCES_PATH_COMPONENT)
             .join(instance_id))
     }
+    /// Directory holding a directory for each template, `templates` in the base path unless the
+    /// `templates-path` config value is set.
+    fn get_templates_path() -> Result<PathBuf, Error> {
+        if let Some(templates_path) =
+            wasi::config::store::get(TEMPLATES_PATH_KEY).map_err(|e| Error::from(e.to_string()))?
+        {
+            return Ok(PathBuf::from(templates_path));
+        }
+        let base_path = wasi::config::store::get(PATH_KEY)
+            .map_err(|e| Error::from(e.to_string()))?
+            .unwrap_or(String::from(PATH_DEFAULT));
+
+        Ok(PathBuf::new()
+            .join(base_path)
+            .join(TEMPLATES_PATH_COMPONENT))
+    }
     fn get_data_path(instance_id: ServiceInstanceId) -> Result<PathBuf, Error> {
         Ok(FilesystemService::get_instance_path(instance_id)?.join(DATA_PATH_COMPONENT))
     }
+    fn get_instance_metadata_path(instance_id: ServiceInstanceId) -> Result<PathBuf, Error> {
+        Ok(FilesystemService::get_instance_path(instance_id)?
+            .join(INSTANCE_METADATA_PATH_COMPONENT))
+    }
+    fn get_quotas_path(instance_id: ServiceInstanceId) -> Result<PathBuf, Error> {
+        Ok(FilesystemService::get_instance_path(instance_id)?.join(QUOTAS_PATH_COMPONENT))
+    }
+    fn get_read_only_path(instance_id: ServiceInstanceId) -> Result<PathBuf, Error> {
+        Ok(FilesystemService::get_instance_path(instance_id)?.join(READ_ONLY_PATH_COMPONENT))
+    }
     fn get_bindings_path(instance_id: ServiceInstanceId) -> Result<PathBuf, Error> {
         Ok(FilesystemService::get_instance_path(instance_id)?.join(BINDINGS_PATH_COMPONENT))
     }
@@ -40,8 +192,278 @@ impl FilesystemService {
         instance_id: ServiceInstanceId,
         binding_id: ServiceBindingId,
     ) -> Result<PathBuf, Error> {
+        service_ids::validate_id(&binding_id)?;
         Ok(FilesystemService::get_bindings_path(instance_id)?.join(binding_id))
     }
+    fn get_binding_metadata_path(
+        instance_id: ServiceInstanceId,
+        binding_id: ServiceBindingId,
+    ) -> Result<PathBuf, Error> {
+        service_ids::validate_id(&binding_id)?;
+        Ok(FilesystemService::get_bindings_path(instance_id)?.join(format!("{binding_id}.json")))
+    }
+    fn require_instance(instance_id: ServiceInstanceId) -> Result<(), Error> {
+        if !FilesystemService::get_data_path(instance_id.clone())?.is_dir() {
+            Err(Error::from(format!("instance '{instance_id}' not found")))?;
+        }
+        Ok(())
+    }
+
+    fn require_new_instance(instance_id: ServiceInstanceId) -> Result<(), Error> {
+        if fs::symlink_metadata(FilesystemService::get_instance_path(instance_id.clone())?).is_ok()
+        {
+            Err(Error::from(format!(
+                "instance '{instance_id}' already exists"
+            )))?;
+        }
+        Ok(())
+    }
+
+    /// Create the directories and records of a new instance in its instance directory, which was
+    /// created by the caller.
+    fn create_instance(
+        instance_id: ServiceInstanceId,
+        instance: &InstanceMetadata,
+        quotas: &InstanceQuotas,
+        template: Option<PathBuf>,
+    ) -> Result<(), Error> {
+        let data_path = FilesystemService::get_data_path(instance_id.clone())?;
+        fs::create_dir(&data_path).map_err(|e| Error::from(e.to_string()))?;
+        fs::create_dir(FilesystemService::get_bindings_path(instance_id.clone())?)
+            .map_err(|e| Error::from(e.to_string()))?;
+        if let Some(template) = template {
+            FilesystemService::copy_template(&template, &data_path)?;
+        }
+        quotas.save(&FilesystemService::get_quotas_path(instance_id.clone())?)?;
+        instance.save(&FilesystemService::get_instance_metadata_path(instance_id)?)
+    }
+
+    fn validate_scopes(scopes: Option<Vec<Scope>>) -> Result<Vec<Scope>, Error> {
+        let mut scopes = scopes.unwrap_or_default();
+        let mut sub_paths = 0;
+        for scope in scopes.iter() {
+            match scope.strip_prefix(PATH_SCOPE_PREFIX) {
+                Some(sub_path) => {
+                    FilesystemService::validate_sub_path(sub_path)?;
+                    sub_paths += 1;
+                }
+                None if scope == READ_SCOPE || scope == WRITE_SCOPE => {}
+                None => Err(Error::from(format!(
+                    "a scope must be one of: {READ_SCOPE}, {WRITE_SCOPE}, {PATH_SCOPE_PREFIX}<sub-path>"
+                )))?,
+            }
+        }
+        if sub_paths > 1 {
+            Err(Error::from("at most one path scope is allowed"))?;
+        }
+        // bindings are read-write unless the read or write scope is requested
+        if !scopes.iter().any(|s| s == READ_SCOPE || s == WRITE_SCOPE) {
+            scopes.splice(0..0, [Scope::from(READ_SCOPE), Scope::from(WRITE_SCOPE)]);
+        }
+        Ok(scopes)
+    }
+    /// A sub-path must stay within the data directory, only normal components are allowed.
+    fn validate_sub_path(sub_path: &str) -> Result<PathBuf, Error> {
+        let path = PathBuf::from(sub_path);
+        if sub_path.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            Err(Error::from(format!(
+                "path '{sub_path}' must be relative to the instance data, without '.' or '..' components"
+            )))?;
+        }
+        Ok(path)
+    }
+    /// Names of the templates instances may be seeded from.
+    fn list_templates() -> Result<Vec<String>, Error> {
+        let dir = match fs::read_dir(FilesystemService::get_templates_path()?) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => Err(Error::from(e.to_string()))?,
+        };
+        let mut templates = vec![];
+        for entry in dir {
+            let entry = entry.map_err(|e| Error::from(e.to_string()))?;
+            let is_dir = entry
+                .file_type()
+                .map_err(|e| Error::from(e.to_string()))?
+                .is_dir();
+            if let (true, Some(name)) = (is_dir, entry.file_name().to_str()) {
+                templates.push(name.to_string());
+            }
+        }
+        templates.sort();
+        Ok(templates)
+    }
+    /// Only the names of template directories are accepted, ruling out paths that lead outside
+    /// of the templates directory.
+    fn validate_template(template: &str) -> Result<PathBuf, Error> {
+        let templates = FilesystemService::list_templates()?;
+        if !templates.iter().any(|t| t == template) {
+            Err(Error::from(format!(
+                "a template must be one of: {}",
+                templates.join(", ")
+            )))?;
+        }
+        Ok(FilesystemService::get_templates_path()?.join(template))
+    }
+    /// Copy a template's directory tree. Links are refused, they could lead outside the template.
+    fn copy_template(from: &Path, to: &Path) -> Result<(), Error> {
+        for entry in fs::read_dir(from).map_err(|e| Error::from(e.to_string()))? {
+            let entry = entry.map_err(|e| Error::from(e.to_string()))?;
+            let file_type = entry.file_type().map_err(|e| Error::from(e.to_string()))?;
+            let to = to.join(entry.file_name());
+            if file_type.is_dir() {
+                fs::create_dir(&to).map_err(|e| Error::from(e.to_string()))?;
+                FilesystemService::copy_template(&entry.path(), &to)?;
+            } else if file_type.is_file() {
+                fs::copy(entry.path(), &to).map_err(|e| Error::from(e.to_string()))?;
+            } else {
+                Err(Error::from(format!(
+                    "template entry '{}' must be a file or directory",
+                    entry.path().display()
+                )))?;
+            }
+        }
+        Ok(())
+    }
+    /// Create the sub-path within the data directory, refusing to follow links that could lead
+    /// outside of it.
+    fn create_sub_path(instance_id: ServiceInstanceId, sub_path: &Path) -> Result<(), Error> {
+        let mut path = FilesystemService::get_data_path(instance_id)?;
+        for component in sub_path.components() {
+            path.push(component);
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => Err(Error::from(format!(
+                    "path '{}' is not a directory",
+                    sub_path.display()
+                )))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    fs::create_dir(&path).map_err(|e| Error::from(e.to_string()))?
+                }
+                Err(e) => Err(Error::from(e.to_string()))?,
+            }
+        }
+        Ok(())
+    }
+    /// Scopes of an existing binding, as recorded by its metadata. Bindings created before
+    /// metadata was recorded derive their scopes from the target of their link.
+    fn binding_scopes(
+        instance_id: ServiceInstanceId,
+        binding_id: ServiceBindingId,
+    ) -> Result<Vec<Scope>, Error> {
+        if let Some(binding) = BindingMetadata::load(
+            &FilesystemService::get_binding_metadata_path(instance_id.clone(), binding_id.clone())?,
+        )? {
+            return Ok(binding.scopes);
+        }
+        let target = fs::read_link(FilesystemService::get_binding_path(
+            instance_id,
+            binding_id,
+        )?)
+        .map_err(|e| Error::from(e.to_string()))?;
+        let read_only_target = PathBuf::from("..").join(READ_ONLY_PATH_COMPONENT);
+        let (mut scopes, sub_path) = match target.strip_prefix(&read_only_target) {
+            Ok(sub_path) => (vec![Scope::from(READ_SCOPE)], sub_path),
+            Err(_) => (
+                vec![Scope::from(READ_SCOPE), Scope::from(WRITE_SCOPE)],
+                target
+                    .strip_prefix(PathBuf::from("..").join(DATA_PATH_COMPONENT))
+                    .unwrap_or(Path::new("")),
+            ),
+        };
+        if !sub_path.as_os_str().is_empty() {
+            scopes.push(format!("{PATH_SCOPE_PREFIX}{}", sub_path.display()));
+        }
+        Ok(scopes)
+    }
+
+    /// Credentials published for a binding. Bindings without the write scope, or of an instance
+    /// restricted for exceeding its quota, are flagged read-only, which the filesystem-read-only
+    /// client enforces.
+    fn binding_credentials(
+        instance_id: ServiceInstanceId,
+        binding_id: ServiceBindingId,
+        scopes: &[Scope],
+        restricted: bool,
+    ) -> Result<Vec<Credential>, Error> {
+        let mut credentials = vec![
+            Credential {
+                key: String::from("type"),
+                value: String::from("filesystem"),
+            },
+            Credential {
+                key: String::from("path"),
+                value: FilesystemService::get_binding_path(
+                    instance_id.clone(),
+                    binding_id.clone(),
+                )?
+                .into_os_string()
+                .into_string()
+                .unwrap(),
+            },
+            Credential {
+                key: String::from("instance-id"),
+                value: instance_id.clone(),
+            },
+            Credential {
+                key: String::from("binding-id"),
+                value: binding_id.clone(),
+            },
+            Credential {
+                key: String::from("scopes"),
+                value: scopes.join(","),
+            },
+            Credential {
+                key: String::from("issued-at"),
+                value: metadata::timestamp(),
+            },
+        ];
+        if restricted || !scopes.iter().any(|s| s == WRITE_SCOPE) {
+            credentials.push(Credential {
+                key: String::from("read-only"),
+                value: String::from("true"),
+            });
+        }
+        Ok(credentials)
+    }
+
+    /// Count the files under a directory and the bytes they consume. Symbolic links are counted
+    /// as files, but not followed.
+    fn measure_usage(path: &Path) -> Result<(u64, u64), Error> {
+        let mut files = 0;
+        let mut bytes = 0;
+        for entry in fs::read_dir(path).map_err(|e| Error::from(e.to_string()))? {
+            let entry = entry.map_err(|e| Error::from(e.to_string()))?;
+            let metadata =
+                fs::symlink_metadata(entry.path()).map_err(|e| Error::from(e.to_string()))?;
+            if metadata.is_dir() {
+                let (dir_files, dir_bytes) = FilesystemService::measure_usage(&entry.path())?;
+                files += dir_files;
+                bytes += dir_bytes;
+            } else {
+                files += 1;
+                bytes += metadata.len();
+            }
+        }
+        Ok((files, bytes))
+    }
+
+    /// Republish the credentials of every binding of the instance, flagging them read-only while
+    /// the instance is restricted.
+    fn restrict_bindings(instance_id: ServiceInstanceId, restricted: bool) -> Result<(), Error> {
+        for binding_id in FilesystemService::list_bindings(instance_id.clone())? {
+            let scopes =
+                FilesystemService::binding_scopes(instance_id.clone(), binding_id.clone())?;
+            let credentials = FilesystemService::binding_credentials(
+                instance_id.clone(),
+                binding_id.clone(),
+                &scopes,
+                restricted,
+            )?;
+            publish(&binding_id, credentials.as_slice())?;
+        }
+        Ok(())
+    }
 }
 
 impl Lifecycle for FilesystemService {
@@ -55,40 +477,95 @@ impl Lifecycle for FilesystemService {
             Err(Error::from("only 'filesystem' types are supported"))?;
         }
 
-        if tier.is_some() {
-            Err(Error::from("tier is not supported"))?;
+        let instance = InstanceMetadata::new(&type_, tier.as_ref(), requests.as_deref());
+        // the template is not a quota, it only applies when provisioning
+        let (templates, requests): (Vec<Request>, Vec<Request>) = requests
+            .unwrap_or_default()
+            .into_iter()
+            .partition(|r| r.key == TEMPLATE_REQUEST);
+        let template = match templates.as_slice() {
+            [] => None,
+            [template] => Some(FilesystemService::validate_template(&template.value)?),
+            _ => Err(Error::from("at most one template request is allowed"))?,
+        };
+        let quotas = InstanceQuotas::from_tier(tier, Some(requests))?;
+
+        // the instance directory is created by this call, so a failure only removes what this
+        // call created and never an existing instance
+        FilesystemService::require_new_instance(instance_id.clone())?;
+        let instance_path = FilesystemService::get_instance_path(instance_id.clone())?;
+        if let Some(instances_path) = instance_path.parent() {
+            fs::create_dir_all(instances_path).map_err(|e| Error::from(e.to_string()))?;
         }
-        if requests.is_some() {
-            Err(Error::from("requests are not supported"))?;
+        fs::create_dir(&instance_path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                Error::from(format!("instance '{instance_id}' already exists"))
+            }
+            _ => Error::from(e.to_string()),
+        })?;
+        if let Err(e) =
+            FilesystemService::create_instance(instance_id, &instance, &quotas, template)
+        {
+            // a partially provisioned instance is not usable
+            let _ = fs::remove_dir_all(instance_path);
+            Err(e)?;
         }
 
-        fs::create_dir_all(FilesystemService::get_data_path(instance_id.clone())?)
-            .map_err(|e| Error::from(e.to_string()))?;
-        fs::create_dir_all(FilesystemService::get_bindings_path(instance_id.clone())?)
-            .map_err(|e| Error::from(e.to_string()))?;
-
         Ok(())
     }
 
     fn update(
-        _instance_id: ServiceInstanceId,
+        instance_id: ServiceInstanceId,
         tier: Option<Tier>,
         requests: Option<Vec<Request>>,
     ) -> Result<(), Error> {
-        if tier.is_some() {
-            Err(Error::from("tier is not supported"))?;
-        }
-        if requests.is_some() {
-            Err(Error::from("requests are not supported"))?;
+        FilesystemService::require_instance(instance_id.clone())?;
+        if requests.iter().flatten().any(|r| r.key == TEMPLATE_REQUEST) {
+            Err(Error::from(format!(
+                "request '{TEMPLATE_REQUEST}' is only supported when provisioning"
+            )))?;
         }
 
-        // nothing is updatable
+        // an omitted tier retains the current tier, the restriction is reviewed by enforce-quotas
+        let quotas_path = FilesystemService::get_quotas_path(instance_id.clone())?;
+        let current = InstanceQuotas::load(&quotas_path)?;
+        let instance_path = FilesystemService::get_instance_metadata_path(instance_id.clone())?;
+        let mut instance = InstanceMetadata::load(&instance_path)?.unwrap_or(InstanceMetadata {
+            type_: String::from("filesystem"),
+            tier: None,
+            requests: Default::default(),
+            created_at: None,
+            updated_at: None,
+        });
+        let tier = tier.or(instance.tier.clone());
+        instance.update(tier.as_ref(), requests.as_deref());
+        let mut quotas = InstanceQuotas::from_tier(tier, requests)?;
+        quotas.restricted = current.restricted;
+        quotas.save(&quotas_path)?;
+        instance.save(&instance_path)?;
+
         Ok(())
     }
 
     fn destroy(instance_id: ServiceInstanceId, retain: Option<bool>) -> Result<(), Error> {
-        fs::remove_dir(FilesystemService::get_bindings_path(instance_id.clone())?)
-            .map_err(|e| Error::from(e.to_string()))?;
+        // each step removes what it has completed, calling destroy again after a failure resumes
+        // with the remaining bindings and directories
+        let bindings_path = FilesystemService::get_bindings_path(instance_id.clone())?;
+        if bindings_path.is_dir() {
+            for binding_id in FilesystemService::list_bindings(instance_id.clone())? {
+                FilesystemService::unbind(binding_id, instance_id.clone())?;
+            }
+            // metadata records left by an interrupted unbind outlive their link, the credentials
+            // of the binding were destroyed first
+            fs::remove_dir_all(bindings_path).map_err(|e| Error::from(e.to_string()))?;
+        } else if !FilesystemService::get_instance_path(instance_id.clone())?.is_dir() {
+            Err(Error::from(format!("instance '{instance_id}' not found")))?;
+        }
+
+        let read_only_path = FilesystemService::get_read_only_path(instance_id.clone())?;
+        if fs::symlink_metadata(&read_only_path).is_ok() {
+            fs::remove_file(read_only_path).map_err(|e| Error::from(e.to_string()))?;
+        }
 
         if retain.unwrap_or(false) {
             return Ok(());
@@ -105,61 +582,80 @@ impl Lifecycle for FilesystemService {
         instance_id: ServiceInstanceId,
         scopes: Option<Vec<Scope>>,
     ) -> Result<(), Error> {
-        if scopes.is_some() {
-            Err(Error::from("scopes are not supported"))?;
-        }
+        service_ids::validate_id(&binding_id)?;
+        let scopes = FilesystemService::validate_scopes(scopes)?;
 
-        let credentials = vec![
-            Credential {
-                key: String::from("type"),
-                value: String::from("filesystem"),
-            },
-            Credential {
-                key: String::from("path"),
-                value: FilesystemService::get_binding_path(
-                    instance_id.clone(),
-                    binding_id.clone(),
-                )?
-                .into_os_string()
-                .into_string()
-                .unwrap(),
-            },
-            Credential {
-                key: String::from("instance-id"),
-                value: instance_id.clone(),
-            },
-            Credential {
-                key: String::from("binding-id"),
-                value: binding_id.clone(),
-            },
-            Credential {
-                key: String::from("issued-at"),
-                value: DateTime::from_timestamp(now().seconds as i64, 0)
-                    .expect("valid wall clock time")
-                    .format("%Y-%m-%dT%H:%M:%SZ")
-                    .to_string(),
-            },
-        ];
+        let quotas =
+            InstanceQuotas::load(&FilesystemService::get_quotas_path(instance_id.clone())?)?;
+        let credentials = FilesystemService::binding_credentials(
+            instance_id.clone(),
+            binding_id.clone(),
+            &scopes,
+            quotas.restricted,
+        )?;
 
+        // read-only bindings link to the data directory through the read-only link
+        let target = match scopes.iter().any(|s| s == WRITE_SCOPE) {
+            true => DATA_PATH_COMPONENT,
+            false => {
+                let read_only_path = FilesystemService::get_read_only_path(instance_id.clone())?;
+                if fs::symlink_metadata(&read_only_path).is_err() {
+                    #[allow(deprecated)]
+                    fs::soft_link(DATA_PATH_COMPONENT, &read_only_path)
+                        .map_err(|e| Error::from(e.to_string()))?;
+                }
+                READ_ONLY_PATH_COMPONENT
+            }
+        };
+        let mut target = PathBuf::from("..").join(target);
+        // the binding is rooted at the sub-path of the data directory
+        if let Some(sub_path) = scopes
+            .iter()
+            .find_map(|s| s.strip_prefix(PATH_SCOPE_PREFIX))
+        {
+            let sub_path = FilesystemService::validate_sub_path(sub_path)?;
+            FilesystemService::create_sub_path(instance_id.clone(), &sub_path)?;
+            target.push(sub_path);
+        }
         // TODO soft_link is deprecated, but the replacements are not supported for wasm32
         #[allow(deprecated)]
         fs::soft_link(
-            "../data",
+            target,
             FilesystemService::get_binding_path(instance_id.clone(), binding_id.clone())?,
         )
         .map_err(|e| Error::from(e.to_string()))?;
+        BindingMetadata::new(&scopes).save(&FilesystemService::get_binding_metadata_path(
+            instance_id.clone(),
+            binding_id.clone(),
+        )?)?;
         publish(&binding_id, credentials.as_slice())?;
 
         Ok(())
     }
 
     fn unbind(binding_id: ServiceBindingId, instance_id: ServiceInstanceId) -> Result<(), Error> {
+        service_ids::validate_id(&binding_id)?;
+        service_ids::validate_id(&instance_id)?;
+        let binding_path =
+            FilesystemService::get_binding_path(instance_id.clone(), binding_id.clone())?;
+        let metadata_path =
+            FilesystemService::get_binding_metadata_path(instance_id, binding_id.clone())?;
+        if fs::symlink_metadata(&binding_path).is_err() && !metadata_path.is_file() {
+            Err(Error::from(format!("binding '{binding_id}' not found")))?;
+        }
+
+        // each step tolerates what an earlier, interrupted, unbind already removed, bindings
+        // created before metadata was recorded have none
         destroy(&binding_id)?;
-        fs::remove_file(FilesystemService::get_binding_path(
-            instance_id,
-            binding_id,
-        )?)
-        .map_err(|e| Error::from(e.to_string()))
+        for path in [binding_path, metadata_path] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(Error::from(e.to_string()))?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
     }
 
     fn list_bindings(instance_id: ServiceInstanceId) -> Result<Vec<ServiceBindingId>, Error> {
@@ -168,6 +664,14 @@ impl Lifecycle for FilesystemService {
         let mut binding_ids: Vec<ServiceBindingId> = vec![];
         for file in dir {
             let file = file.map_err(|e| Error::from(e.to_string()))?;
+            // each binding is a link, next to its metadata record
+            if !file
+                .file_type()
+                .map_err(|e| Error::from(e.to_string()))?
+                .is_symlink()
+            {
+                continue;
+            }
             let binding_id: ServiceBindingId = file.file_name().to_str().unwrap().into();
             binding_ids.push(binding_id);
         }
@@ -176,6 +680,75 @@ impl Lifecycle for FilesystemService {
     }
 }
 
+impl Catalog for FilesystemService {
+    fn offerings() -> Result<Vec<Offering>, Error> {
+        Ok(vec![Offering {
+            type_: String::from("filesystem"),
+            tiers: TIER_QUOTAS.iter().map(|t| Tier::from(t.tier)).collect(),
+            requests: vec![
+                RequestOption {
+                    key: String::from(MAX_FILES_REQUEST),
+                    description: String::from(
+                        "Maximum number of files, lowering the limit of the tier",
+                    ),
+                    allowed_values: None,
+                },
+                RequestOption {
+                    key: String::from(MAX_BYTES_REQUEST),
+                    description: String::from(
+                        "Maximum number of bytes, lowering the limit of the tier",
+                    ),
+                    allowed_values: None,
+                },
+                RequestOption {
+                    key: String::from(TEMPLATE_REQUEST),
+                    description: String::from(
+                        "Template the data of the instance is seeded from when provisioning",
+                    ),
+                    allowed_values: Some(FilesystemService::list_templates()?),
+                },
+            ],
+            scopes: vec![
+                Scope::from(READ_SCOPE),
+                Scope::from(WRITE_SCOPE),
+                format!("{PATH_SCOPE_PREFIX}<sub-path>"),
+            ],
+        }])
+    }
+}
+
+impl Quotas for FilesystemService {
+    fn enforce_quotas(instance_id: ServiceInstanceId) -> Result<QuotaUsage, Error> {
+        FilesystemService::require_instance(instance_id.clone())?;
+
+        let quotas_path = FilesystemService::get_quotas_path(instance_id.clone())?;
+        let mut quotas = InstanceQuotas::load(&quotas_path)?;
+        let (files, bytes) = FilesystemService::measure_usage(&FilesystemService::get_data_path(
+            instance_id.clone(),
+        )?)?;
+
+        // with `restrict-over-quota` disabled usage is reported, but bindings are not restricted
+        let restrict = wasi::config::store::get(RESTRICT_KEY)
+            .map_err(|e| Error::from(e.to_string()))?
+            .map(|r| r == "true")
+            .unwrap_or(true);
+        let exceeded = restrict && quotas.is_exceeded(files, bytes);
+        if exceeded != quotas.restricted {
+            FilesystemService::restrict_bindings(instance_id, exceeded)?;
+            quotas.restricted = exceeded;
+            quotas.save(&quotas_path)?;
+        }
+
+        Ok(QuotaUsage {
+            max_items: quotas.max_files,
+            max_bytes: quotas.max_bytes,
+            items: files,
+            bytes,
+            restricted: exceeded,
+        })
+    }
+}
+
 wit_bindgen::generate!({
     path: "../wit",
     world: "filesystem-lifecycle",
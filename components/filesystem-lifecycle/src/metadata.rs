@@ -0,0 +1,156 @@
+use crate::componentized::services::types::{Request, Scope, Tier};
+use crate::exports::componentized::services::lifecycle::Error;
+use crate::wasi::clocks::wall_clock::now;
+use chrono::DateTime;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Record describing an instance, stored as `instance.json` in the instance directory.
+pub(crate) struct InstanceMetadata {
+    pub(crate) type_: String,
+    pub(crate) tier: Option<Tier>,
+    pub(crate) requests: BTreeMap<String, String>,
+    /// Absent for instances provisioned before metadata was recorded.
+    pub(crate) created_at: Option<String>,
+    pub(crate) updated_at: Option<String>,
+}
+
+impl InstanceMetadata {
+    pub(crate) fn new(type_: &str, tier: Option<&Tier>, requests: Option<&[Request]>) -> Self {
+        let timestamp = timestamp();
+        InstanceMetadata {
+            type_: type_.to_string(),
+            tier: tier.cloned(),
+            requests: requests_map(requests),
+            created_at: Some(timestamp.clone()),
+            updated_at: Some(timestamp),
+        }
+    }
+
+    /// Record a change to the tier and requests.
+    pub(crate) fn update(&mut self, tier: Option<&Tier>, requests: Option<&[Request]>) {
+        self.tier = tier.cloned();
+        self.requests = requests_map(requests);
+        self.updated_at = Some(timestamp());
+    }
+
+    /// Read the record, instances provisioned before metadata was recorded have none.
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>, Error> {
+        let Some(fields) = read_object(path)? else {
+            return Ok(None);
+        };
+        Ok(Some(InstanceMetadata {
+            type_: fields["type"].as_str().unwrap_or_default().to_string(),
+            tier: fields["tier"].as_str().map(Tier::from),
+            requests: match &fields["requests"] {
+                Value::Object(requests) => requests
+                    .iter()
+                    .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                    .collect(),
+                _ => BTreeMap::new(),
+            },
+            created_at: fields["created-at"].as_str().map(String::from),
+            updated_at: fields["updated-at"].as_str().map(String::from),
+        }))
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), Error> {
+        let instance = json!({
+            "type": self.type_,
+            "tier": self.tier,
+            "requests": self.requests,
+            "created-at": self.created_at,
+            "updated-at": self.updated_at,
+        });
+        write_atomically(path, &instance.to_string())
+    }
+}
+
+/// Record describing a binding, stored as `{binding-id}.json` next to the binding's link.
+pub(crate) struct BindingMetadata {
+    pub(crate) scopes: Vec<Scope>,
+    pub(crate) created_at: String,
+}
+
+impl BindingMetadata {
+    pub(crate) fn new(scopes: &[Scope]) -> Self {
+        BindingMetadata {
+            scopes: scopes.to_vec(),
+            created_at: timestamp(),
+        }
+    }
+
+    /// Read the record, bindings created before metadata was recorded have none.
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>, Error> {
+        let Some(fields) = read_object(path)? else {
+            return Ok(None);
+        };
+        Ok(Some(BindingMetadata {
+            scopes: match &fields["scopes"] {
+                Value::Array(scopes) => scopes
+                    .iter()
+                    .filter_map(|s| s.as_str().map(Scope::from))
+                    .collect(),
+                _ => Err(Error::from(format!(
+                    "'{}' must record the scopes of the binding",
+                    path.display()
+                )))?,
+            },
+            created_at: fields["created-at"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        }))
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), Error> {
+        let binding = json!({
+            "scopes": self.scopes,
+            "created-at": self.created_at,
+        });
+        write_atomically(path, &binding.to_string())
+    }
+}
+
+/// Read a JSON object from a file, `None` if the file does not exist.
+pub(crate) fn read_object(path: &Path) -> Result<Option<Value>, Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => Err(Error::from(e.to_string()))?,
+    };
+    match serde_json::from_str(&contents).map_err(|e| Error::from(e.to_string()))? {
+        Value::Object(fields) => Ok(Some(Value::Object(fields))),
+        _ => Err(Error::from(format!(
+            "'{}' must contain a JSON object",
+            path.display()
+        ))),
+    }
+}
+
+/// Replace the contents of a file, readers either see the previous or the new contents. The
+/// contents are written to a temporary file which is renamed over the file.
+pub(crate) fn write_atomically(path: &Path, contents: &str) -> Result<(), Error> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, contents).map_err(|e| Error::from(e.to_string()))?;
+    fs::rename(&temp_path, path).map_err(|e| Error::from(e.to_string()))
+}
+
+/// Current wall clock time, formatted as an RFC 3339 UTC timestamp.
+pub(crate) fn timestamp() -> String {
+    DateTime::from_timestamp(now().seconds as i64, 0)
+        .expect("valid wall clock time")
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+fn requests_map(requests: Option<&[Request]>) -> BTreeMap<String, String> {
+    requests
+        .unwrap_or_default()
+        .iter()
+        .map(|r| (r.key.clone(), r.value.clone()))
+        .collect()
+}
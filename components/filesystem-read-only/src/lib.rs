@@ -0,0 +1,407 @@
+#![no_main]
+
+use exports::wasi::filesystem::preopens::Guest as Preopens;
+use exports::wasi::filesystem::types::{
+    Advice, Descriptor, DescriptorBorrow, DescriptorFlags, DescriptorStat, DescriptorType,
+    DirectoryEntry, DirectoryEntryStream, ErrorCode, Filesize, Guest as Types, GuestDescriptor,
+    GuestDirectoryEntryStream, MetadataHashValue, NewTimestamp, OpenFlags, PathFlags,
+};
+use wasi::filesystem::{preopens, types};
+use wasi::io::streams::{Error, InputStream, OutputStream};
+
+const READ_ONLY_KEY: &str = "read-only";
+
+struct ReadOnlyFilesystem;
+
+impl ReadOnlyFilesystem {
+    /// Bindings are published with `read-only=true` when they may not modify the service.
+    fn read_only() -> bool {
+        matches!(
+            wasi::config::store::get(READ_ONLY_KEY)
+                .as_ref()
+                .map(Option::as_deref),
+            Ok(Some("true"))
+        )
+    }
+}
+
+impl Preopens for ReadOnlyFilesystem {
+    fn get_directories() -> Vec<(Descriptor, String)> {
+        let read_only = Self::read_only();
+        preopens::get_directories()
+            .into_iter()
+            .map(|(inner, path)| {
+                (
+                    Descriptor::new(ReadOnlyDescriptor { inner, read_only }),
+                    path,
+                )
+            })
+            .collect()
+    }
+}
+
+impl Types for ReadOnlyFilesystem {
+    type Descriptor = ReadOnlyDescriptor;
+    type DirectoryEntryStream = ReadOnlyDirectoryEntryStream;
+
+    fn filesystem_error_code(err: &Error) -> Option<ErrorCode> {
+        types::filesystem_error_code(err).map(ErrorCode::from)
+    }
+}
+
+/// A descriptor of the wrapped filesystem. Descriptors opened from a read-only descriptor are
+/// read-only as well.
+struct ReadOnlyDescriptor {
+    inner: types::Descriptor,
+    read_only: bool,
+}
+
+impl ReadOnlyDescriptor {
+    fn check_writable(&self) -> Result<(), ErrorCode> {
+        match self.read_only {
+            true => Err(ErrorCode::ReadOnly),
+            false => Ok(()),
+        }
+    }
+}
+
+impl GuestDescriptor for ReadOnlyDescriptor {
+    fn read_via_stream(&self, offset: Filesize) -> Result<InputStream, ErrorCode> {
+        Ok(self.inner.read_via_stream(offset)?)
+    }
+
+    fn write_via_stream(&self, offset: Filesize) -> Result<OutputStream, ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.write_via_stream(offset)?)
+    }
+
+    fn append_via_stream(&self) -> Result<OutputStream, ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.append_via_stream()?)
+    }
+
+    fn advise(&self, offset: Filesize, length: Filesize, advice: Advice) -> Result<(), ErrorCode> {
+        Ok(self.inner.advise(offset, length, advice.into())?)
+    }
+
+    fn sync_data(&self) -> Result<(), ErrorCode> {
+        Ok(self.inner.sync_data()?)
+    }
+
+    fn get_flags(&self) -> Result<DescriptorFlags, ErrorCode> {
+        let flags = DescriptorFlags::from(self.inner.get_flags()?);
+        match self.read_only {
+            true => Ok(flags - DescriptorFlags::WRITE - DescriptorFlags::MUTATE_DIRECTORY),
+            false => Ok(flags),
+        }
+    }
+
+    fn get_type(&self) -> Result<DescriptorType, ErrorCode> {
+        Ok(self.inner.get_type()?.into())
+    }
+
+    fn set_size(&self, size: Filesize) -> Result<(), ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.set_size(size)?)
+    }
+
+    fn set_times(
+        &self,
+        data_access_timestamp: NewTimestamp,
+        data_modification_timestamp: NewTimestamp,
+    ) -> Result<(), ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.set_times(
+            data_access_timestamp.into(),
+            data_modification_timestamp.into(),
+        )?)
+    }
+
+    fn read(&self, length: Filesize, offset: Filesize) -> Result<(Vec<u8>, bool), ErrorCode> {
+        Ok(self.inner.read(length, offset)?)
+    }
+
+    fn write(&self, buffer: Vec<u8>, offset: Filesize) -> Result<Filesize, ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.write(&buffer, offset)?)
+    }
+
+    fn read_directory(&self) -> Result<DirectoryEntryStream, ErrorCode> {
+        Ok(DirectoryEntryStream::new(ReadOnlyDirectoryEntryStream {
+            inner: self.inner.read_directory()?,
+        }))
+    }
+
+    fn sync(&self) -> Result<(), ErrorCode> {
+        Ok(self.inner.sync()?)
+    }
+
+    fn create_directory_at(&self, path: String) -> Result<(), ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.create_directory_at(&path)?)
+    }
+
+    fn stat(&self) -> Result<DescriptorStat, ErrorCode> {
+        Ok(self.inner.stat()?.into())
+    }
+
+    fn stat_at(&self, path_flags: PathFlags, path: String) -> Result<DescriptorStat, ErrorCode> {
+        Ok(self.inner.stat_at(path_flags.into(), &path)?.into())
+    }
+
+    fn set_times_at(
+        &self,
+        path_flags: PathFlags,
+        path: String,
+        data_access_timestamp: NewTimestamp,
+        data_modification_timestamp: NewTimestamp,
+    ) -> Result<(), ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.set_times_at(
+            path_flags.into(),
+            &path,
+            data_access_timestamp.into(),
+            data_modification_timestamp.into(),
+        )?)
+    }
+
+    fn link_at(
+        &self,
+        old_path_flags: PathFlags,
+        old_path: String,
+        new_descriptor: DescriptorBorrow<'_>,
+        new_path: String,
+    ) -> Result<(), ErrorCode> {
+        self.check_writable()?;
+        let new_descriptor: &ReadOnlyDescriptor = new_descriptor.get();
+        new_descriptor.check_writable()?;
+        Ok(self.inner.link_at(
+            old_path_flags.into(),
+            &old_path,
+            &new_descriptor.inner,
+            &new_path,
+        )?)
+    }
+
+    fn open_at(
+        &self,
+        path_flags: PathFlags,
+        path: String,
+        open_flags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> Result<Descriptor, ErrorCode> {
+        if open_flags.intersects(OpenFlags::CREATE | OpenFlags::EXCLUSIVE | OpenFlags::TRUNCATE)
+            || flags.intersects(DescriptorFlags::WRITE | DescriptorFlags::MUTATE_DIRECTORY)
+        {
+            self.check_writable()?;
+        }
+        let inner =
+            self.inner
+                .open_at(path_flags.into(), &path, open_flags.into(), flags.into())?;
+        Ok(Descriptor::new(ReadOnlyDescriptor {
+            inner,
+            read_only: self.read_only,
+        }))
+    }
+
+    fn readlink_at(&self, path: String) -> Result<String, ErrorCode> {
+        Ok(self.inner.readlink_at(&path)?)
+    }
+
+    fn remove_directory_at(&self, path: String) -> Result<(), ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.remove_directory_at(&path)?)
+    }
+
+    fn rename_at(
+        &self,
+        old_path: String,
+        new_descriptor: DescriptorBorrow<'_>,
+        new_path: String,
+    ) -> Result<(), ErrorCode> {
+        self.check_writable()?;
+        let new_descriptor: &ReadOnlyDescriptor = new_descriptor.get();
+        new_descriptor.check_writable()?;
+        Ok(self
+            .inner
+            .rename_at(&old_path, &new_descriptor.inner, &new_path)?)
+    }
+
+    fn symlink_at(&self, old_path: String, new_path: String) -> Result<(), ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.symlink_at(&old_path, &new_path)?)
+    }
+
+    fn unlink_file_at(&self, path: String) -> Result<(), ErrorCode> {
+        self.check_writable()?;
+        Ok(self.inner.unlink_file_at(&path)?)
+    }
+
+    fn is_same_object(&self, other: DescriptorBorrow<'_>) -> bool {
+        let other: &ReadOnlyDescriptor = other.get();
+        self.inner.is_same_object(&other.inner)
+    }
+
+    fn metadata_hash(&self) -> Result<MetadataHashValue, ErrorCode> {
+        Ok(self.inner.metadata_hash()?.into())
+    }
+
+    fn metadata_hash_at(
+        &self,
+        path_flags: PathFlags,
+        path: String,
+    ) -> Result<MetadataHashValue, ErrorCode> {
+        Ok(self
+            .inner
+            .metadata_hash_at(path_flags.into(), &path)?
+            .into())
+    }
+}
+
+struct ReadOnlyDirectoryEntryStream {
+    inner: types::DirectoryEntryStream,
+}
+
+impl GuestDirectoryEntryStream for ReadOnlyDirectoryEntryStream {
+    fn read_directory_entry(&self) -> Result<Option<DirectoryEntry>, ErrorCode> {
+        Ok(self
+            .inner
+            .read_directory_entry()?
+            .map(|entry| DirectoryEntry {
+                type_: entry.type_.into(),
+                name: entry.name,
+            }))
+    }
+}
+
+// the exported types mirror the imported types
+
+/// Convert between the same enum of the imported and exported interfaces.
+macro_rules! convert_enum {
+    ($from:ty => $to:ty { $($case:ident),* $(,)? }) => {
+        impl From<$from> for $to {
+            fn from(value: $from) -> Self {
+                match value {
+                    $(<$from>::$case => <$to>::$case,)*
+                }
+            }
+        }
+    };
+}
+
+/// Convert between the same flags of the imported and exported interfaces.
+macro_rules! convert_flags {
+    ($from:ty => $to:ty) => {
+        impl From<$from> for $to {
+            fn from(value: $from) -> Self {
+                <$to>::from_bits_retain(value.bits())
+            }
+        }
+    };
+}
+
+convert_enum!(types::DescriptorType => DescriptorType {
+    Unknown,
+    BlockDevice,
+    CharacterDevice,
+    Directory,
+    Fifo,
+    SymbolicLink,
+    RegularFile,
+    Socket,
+});
+
+convert_enum!(Advice => types::Advice {
+    Normal,
+    Sequential,
+    Random,
+    WillNeed,
+    DontNeed,
+    NoReuse,
+});
+
+convert_enum!(types::ErrorCode => ErrorCode {
+    Access,
+    WouldBlock,
+    Already,
+    BadDescriptor,
+    Busy,
+    Deadlock,
+    Quota,
+    Exist,
+    FileTooLarge,
+    IllegalByteSequence,
+    InProgress,
+    Interrupted,
+    Invalid,
+    Io,
+    IsDirectory,
+    Loop,
+    TooManyLinks,
+    MessageSize,
+    NameTooLong,
+    NoDevice,
+    NoEntry,
+    NoLock,
+    InsufficientMemory,
+    InsufficientSpace,
+    NotDirectory,
+    NotEmpty,
+    NotRecoverable,
+    Unsupported,
+    NoTty,
+    NoSuchDevice,
+    Overflow,
+    NotPermitted,
+    Pipe,
+    ReadOnly,
+    InvalidSeek,
+    TextFileBusy,
+    CrossDevice,
+});
+
+convert_flags!(types::DescriptorFlags => DescriptorFlags);
+convert_flags!(DescriptorFlags => types::DescriptorFlags);
+convert_flags!(PathFlags => types::PathFlags);
+convert_flags!(OpenFlags => types::OpenFlags);
+
+impl From<types::DescriptorStat> for DescriptorStat {
+    fn from(stat: types::DescriptorStat) -> Self {
+        DescriptorStat {
+            type_: stat.type_.into(),
+            link_count: stat.link_count,
+            size: stat.size,
+            data_access_timestamp: stat.data_access_timestamp,
+            data_modification_timestamp: stat.data_modification_timestamp,
+            status_change_timestamp: stat.status_change_timestamp,
+        }
+    }
+}
+
+impl From<NewTimestamp> for types::NewTimestamp {
+    fn from(timestamp: NewTimestamp) -> Self {
+        match timestamp {
+            NewTimestamp::NoChange => types::NewTimestamp::NoChange,
+            NewTimestamp::Now => types::NewTimestamp::Now,
+            NewTimestamp::Timestamp(datetime) => types::NewTimestamp::Timestamp(datetime),
+        }
+    }
+}
+
+impl From<types::MetadataHashValue> for MetadataHashValue {
+    fn from(hash: types::MetadataHashValue) -> Self {
+        MetadataHashValue {
+            lower: hash.lower,
+            upper: hash.upper,
+        }
+    }
+}
+
+wit_bindgen::generate!({
+    path: "../wit",
+    world: "filesystem-read-only",
+    features: ["clocks-timezone"],
+    generate_all
+});
+
+export!(ReadOnlyFilesystem);
@@ -1,12 +1,21 @@
 #![no_main]
 
+use credential_envelope::Keys;
 use exports::componentized::services::credential_admin::{Credential, Error, Guest, ServiceId};
+use exports::componentized::services::credential_encryption::Guest as CredentialEncryption;
+use exports::componentized::services::credential_versions::Guest as CredentialVersions;
 use serde_json;
 use std::collections::HashMap;
 use wasi::keyvalue::store::{open, Bucket};
+use wasi::random::random::get_random_bytes;
 
 const BUCKET_KEY: &str = "bucket";
 const BUCKET_DEFAULT: &str = "bindings";
+const ENCRYPTION_KEYS_KEY: &str = "encryption-keys";
+const ENCRYPTION_KEY_ID_KEY: &str = "encryption-key-id";
+const ALLOW_PLAINTEXT_KEY: &str = "allow-plaintext";
+const MAX_VERSIONS_KEY: &str = "max-versions";
+const MAX_VERSIONS_DEFAULT: usize = 10;
 
 pub(crate) struct KeyvalueCredentialAdmin;
 
@@ -18,6 +27,105 @@ impl KeyvalueCredentialAdmin {
         open(&bucket_id).map_err(Self::map_keyvalue_err)
     }
 
+    /// Keys credentials are encrypted with, listed by the `encryption-keys` config value.
+    /// Credentials are stored as plain JSON when no keys are configured. Once keys are configured,
+    /// credentials stored as plain JSON are only read when the `allow-plaintext` config value is
+    /// `true`.
+    fn get_keys() -> Result<Option<Keys>, Error> {
+        let Some(keys) =
+            wasi::config::store::get(ENCRYPTION_KEYS_KEY).map_err(Self::map_config_err)?
+        else {
+            return Ok(None);
+        };
+        let key_id =
+            wasi::config::store::get(ENCRYPTION_KEY_ID_KEY).map_err(Self::map_config_err)?;
+        let allow_plaintext = wasi::config::store::get(ALLOW_PLAINTEXT_KEY)
+            .map_err(Self::map_config_err)?
+            .is_some_and(|v| v == "true");
+        Ok(Some(
+            Keys::parse(&keys, key_id.as_deref())?.allow_plaintext(allow_plaintext),
+        ))
+    }
+
+    /// Number of versions kept for each id, older versions are deleted as new ones are published.
+    fn get_max_versions() -> Result<usize, Error> {
+        let Some(max_versions) =
+            wasi::config::store::get(MAX_VERSIONS_KEY).map_err(Self::map_config_err)?
+        else {
+            return Ok(MAX_VERSIONS_DEFAULT);
+        };
+        match max_versions.parse() {
+            Ok(max_versions) if max_versions > 0 => Ok(max_versions),
+            _ => Err(Error::from(format!(
+                "Config '{MAX_VERSIONS_KEY}' must be a positive number, got: {max_versions}"
+            ))),
+        }
+    }
+
+    fn list_keys(bucket: &Bucket) -> Result<Vec<String>, Error> {
+        let mut keys = vec![];
+        let mut cursor = None;
+        loop {
+            let response = bucket
+                .list_keys(cursor.as_deref())
+                .map_err(Self::map_keyvalue_err)?;
+            keys.extend(response.keys);
+            cursor = response.cursor;
+            if cursor.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    /// Ids may not contain '@', which separates an id from the version in the keys of versions.
+    fn validate_id(id: &str) -> Result<(), Error> {
+        if id.contains('@') {
+            Err(Error::from(format!("id '{id}' must not contain '@'")))?;
+        }
+        Ok(())
+    }
+
+    /// Each version of the credentials is kept at `{id}@{version}`, the latest version is also
+    /// set at the id for the credential store. Ids may not contain '@', so these keys never
+    /// collide with the key of another id.
+    fn version_key(id: &str, version: u64) -> String {
+        format!("{id}@{version}")
+    }
+
+    /// The versions kept for an id are indexed at `{id}@versions`, oldest first.
+    fn versions_key(id: &str) -> String {
+        format!("{id}@versions")
+    }
+
+    fn versions(bucket: &Bucket, id: &str) -> Result<Vec<u64>, Error> {
+        match bucket
+            .get(&Self::versions_key(id))
+            .map_err(Self::map_keyvalue_err)?
+        {
+            Some(versions) => serde_json::from_slice(&versions).map_err(Self::map_serde_err),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn set_versions(bucket: &Bucket, id: &str, versions: &[u64]) -> Result<(), Error> {
+        let versions = serde_json::to_vec(versions).map_err(Self::map_serde_err)?;
+        bucket
+            .set(&Self::versions_key(id), &versions)
+            .map_err(Self::map_keyvalue_err)
+    }
+
+    /// Re-encrypt the record at a key with the current key, unless it already is.
+    fn reencrypt_record(keys: &Keys, bucket: &Bucket, id: &str, key: &str) -> Result<bool, Error> {
+        let Some(record) = bucket.get(key).map_err(Self::map_keyvalue_err)? else {
+            return Ok(false);
+        };
+        let Some(creds) = keys.reencrypt(&record, id.as_bytes(), get_random_bytes)? else {
+            return Ok(false);
+        };
+        bucket.set(key, &creds).map_err(Self::map_keyvalue_err)?;
+        Ok(true)
+    }
+
     fn map_keyvalue_err(e: wasi::keyvalue::store::Error) -> Error {
         match e {
             wasi::keyvalue::store::Error::NoSuchStore => Error::from("NoSuchStore Error"),
@@ -42,21 +150,111 @@ impl KeyvalueCredentialAdmin {
 
 impl Guest for KeyvalueCredentialAdmin {
     fn publish(id: ServiceId, credentials: Vec<Credential>) -> Result<(), Error> {
+        Self::validate_id(&id)?;
+        let max_versions = Self::get_max_versions()?;
         let mut creds = HashMap::new();
         for Credential { key, value } in credentials {
             creds.insert(key, value);
         }
-        let creds = serde_json::to_vec(&creds).map_err(Self::map_serde_err)?;
+        let mut creds = serde_json::to_vec(&creds).map_err(Self::map_serde_err)?;
+        if let Some(keys) = Self::get_keys()? {
+            creds = keys.seal(&creds, id.as_bytes(), get_random_bytes)?;
+        }
 
-        Self::get_bucket()?
-            .set(&id, &creds)
-            .map_err(Self::map_keyvalue_err)
+        let bucket = Self::get_bucket()?;
+        let mut versions = Self::versions(&bucket, &id)?;
+        // credentials published before versions were kept become the first version
+        if versions.is_empty() {
+            if let Some(previous) = bucket.get(&id).map_err(Self::map_keyvalue_err)? {
+                bucket
+                    .set(&Self::version_key(&id, 1), &previous)
+                    .map_err(Self::map_keyvalue_err)?;
+                versions.push(1);
+            }
+        }
+        let version = versions.last().map_or(1, |v| v + 1);
+        bucket
+            .set(&Self::version_key(&id, version), &creds)
+            .map_err(Self::map_keyvalue_err)?;
+        versions.push(version);
+        let pruned: Vec<u64> = versions
+            .drain(..versions.len().saturating_sub(max_versions))
+            .collect();
+        // the index is written before pruning, so it never lists a version that was deleted
+        Self::set_versions(&bucket, &id, &versions)?;
+        for version in pruned {
+            bucket
+                .delete(&Self::version_key(&id, version))
+                .map_err(Self::map_keyvalue_err)?;
+        }
+        bucket.set(&id, &creds).map_err(Self::map_keyvalue_err)
     }
 
     fn destroy(id: ServiceId) -> Result<(), Error> {
-        Self::get_bucket()?
-            .delete(&id)
-            .map_err(Self::map_keyvalue_err)
+        Self::validate_id(&id)?;
+        let bucket = Self::get_bucket()?;
+        for version in Self::versions(&bucket, &id)? {
+            bucket
+                .delete(&Self::version_key(&id, version))
+                .map_err(Self::map_keyvalue_err)?;
+        }
+        bucket
+            .delete(&Self::versions_key(&id))
+            .map_err(Self::map_keyvalue_err)?;
+        bucket.delete(&id).map_err(Self::map_keyvalue_err)
+    }
+}
+
+impl CredentialEncryption for KeyvalueCredentialAdmin {
+    fn reencrypt() -> Result<Vec<ServiceId>, Error> {
+        let keys =
+            Self::get_keys()?.ok_or_else(|| Error::from("no encryption keys are configured"))?;
+        let bucket = Self::get_bucket()?;
+
+        let mut ids = vec![];
+        // keys of versions and their index contain '@', so only the latest credentials are ids
+        for id in Self::list_keys(&bucket)? {
+            if id.contains('@') {
+                continue;
+            }
+            let mut reencrypted = Self::reencrypt_record(&keys, &bucket, &id, &id)?;
+            // previous versions are re-encrypted too, so previous keys can be retired
+            for version in Self::versions(&bucket, &id)? {
+                reencrypted |=
+                    Self::reencrypt_record(&keys, &bucket, &id, &Self::version_key(&id, version))?;
+            }
+            if reencrypted {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+}
+
+impl CredentialVersions for KeyvalueCredentialAdmin {
+    fn list_versions(id: ServiceId) -> Result<Vec<u64>, Error> {
+        Self::validate_id(&id)?;
+        Self::versions(&Self::get_bucket()?, &id)
+    }
+
+    fn fetch_version(id: ServiceId, version: u64) -> Result<Vec<Credential>, Error> {
+        Self::validate_id(&id)?;
+        let record = Self::get_bucket()?
+            .get(&Self::version_key(&id, version))
+            .map_err(Self::map_keyvalue_err)?
+            .ok_or_else(|| {
+                Error::from(format!(
+                    "version {version} of credentials for '{id}' not found"
+                ))
+            })?;
+        let record = credential_envelope::open(Self::get_keys()?.as_ref(), &record, id.as_bytes())?;
+        let creds: HashMap<String, String> =
+            serde_json::from_slice(&record).map_err(Self::map_serde_err)?;
+        Ok(creds
+            .into_iter()
+            .map(|(key, value)| Credential { key, value })
+            .collect())
     }
 }
 
@@ -1,5 +1,6 @@
 #![no_main]
 
+use credential_envelope::Keys;
 use exports::componentized::services::credential_store::{Credential, Error, Guest, ServiceId};
 use serde_json;
 use std::collections::HashMap;
@@ -8,6 +9,8 @@ use wasi::keyvalue::store::{open, Bucket};
 
 const BUCKET_KEY: &str = "bucket";
 const BUCKET_DEFAULT: &str = "bindings";
+const ENCRYPTION_KEYS_KEY: &str = "encryption-keys";
+const ALLOW_PLAINTEXT_KEY: &str = "allow-plaintext";
 
 pub(crate) struct KeyvalueCredentialStore;
 
@@ -19,6 +22,23 @@ impl KeyvalueCredentialStore {
         open(&bucket_id).map_err(Self::map_keyvalue_err)
     }
 
+    /// Keys credentials may be encrypted with, listed by the `encryption-keys` config value. Once
+    /// keys are configured, credentials stored as plain JSON are only read when the
+    /// `allow-plaintext` config value is `true`.
+    fn get_keys() -> Result<Option<Keys>, Error> {
+        let Some(keys) =
+            wasi::config::store::get(ENCRYPTION_KEYS_KEY).map_err(Self::map_config_err)?
+        else {
+            return Ok(None);
+        };
+        let allow_plaintext = wasi::config::store::get(ALLOW_PLAINTEXT_KEY)
+            .map_err(Self::map_config_err)?
+            .is_some_and(|v| v == "true");
+        Ok(Some(
+            Keys::parse(&keys, None)?.allow_plaintext(allow_plaintext),
+        ))
+    }
+
     fn map_keyvalue_err(e: wasi::keyvalue::store::Error) -> Error {
         match e {
             wasi::keyvalue::store::Error::NoSuchStore => Error::from("NoSuchStore Error"),
@@ -43,10 +63,14 @@ impl KeyvalueCredentialStore {
 
 impl Guest for KeyvalueCredentialStore {
     fn fetch(id: ServiceId) -> Result<Vec<Credential>, Error> {
+        if id.contains('@') {
+            Err(Error::from(format!("id '{id}' must not contain '@'")))?;
+        }
         let creds = Self::get_bucket()?
             .get(&id)
             .map_err(Self::map_keyvalue_err)?
             .unwrap_or(vec![]);
+        let creds = credential_envelope::open(Self::get_keys()?.as_ref(), &creds, id.as_bytes())?;
 
         let creds: HashMap<String, String> =
             serde_json::from_slice(&creds.as_slice()).map_err(Self::map_serde_err)?;
@@ -59,6 +83,21 @@ impl Guest for KeyvalueCredentialStore {
             .collect();
         Ok(creds)
     }
+
+    /// Ids are listed a page of keys at a time, using the cursor of the bucket. Versions kept by
+    /// the credential admin at `<id>@<version>` and `<id>@versions` contain '@', which ids may not,
+    /// so are not listed.
+    fn list(cursor: Option<String>) -> Result<(Vec<ServiceId>, Option<String>), Error> {
+        let response = Self::get_bucket()?
+            .list_keys(cursor.as_deref())
+            .map_err(Self::map_keyvalue_err)?;
+        let ids = response
+            .keys
+            .into_iter()
+            .filter(|key| !key.contains('@'))
+            .collect();
+        Ok((ids, response.cursor))
+    }
 }
 
 wit_bindgen::generate!({
@@ -6,6 +6,8 @@ use componentized::services::types::{
 use exports::wasi::cli::run::Guest;
 use regex_lite::Regex;
 use wasi::cli::environment;
+#[cfg(feature = "archive")]
+use wasi::io::streams::StreamError;
 use wasi::logging::logging::{log, Level};
 use wasi::random::random::get_random_bytes;
 
@@ -101,6 +103,53 @@ enum Commands {
         #[arg(required = true)]
         instance_id: ServiceInstanceId,
     },
+
+    /// List denied access attempts for the bindings of a service as JSON
+    #[cfg(feature = "audit")]
+    Audit {
+        /// Identifier for the service instance
+        #[arg(required = true)]
+        instance_id: ServiceInstanceId,
+    },
+
+    /// Measure the usage of a service and enforce its quota, revoking write access while over
+    /// quota, describing the usage as JSON
+    #[cfg(feature = "quotas")]
+    EnforceQuotas {
+        /// Identifier for the service instance
+        #[arg(required = true)]
+        instance_id: ServiceInstanceId,
+    },
+
+    /// Compare the recorded state of a service with the service and published credentials,
+    /// listing discrepancies as JSON
+    #[cfg(feature = "reconcile")]
+    Reconcile {
+        /// Identifier for the service instance
+        #[arg(required = true)]
+        instance_id: ServiceInstanceId,
+
+        /// Repair the discrepancies found.
+        #[arg(short, long)]
+        repair: Option<bool>,
+    },
+
+    /// Export the state of a service as an archive, written to stdout
+    #[cfg(feature = "archive")]
+    #[command(arg_required_else_help = true)]
+    Export {
+        /// Identifier for the service instance
+        #[arg(required = true)]
+        instance_id: ServiceInstanceId,
+    },
+
+    /// Provision a new service instance from an archive, read from stdin
+    #[cfg(feature = "archive")]
+    Import {
+        /// Identifier for the service instance
+        #[arg(long)]
+        instance_id: Option<ServiceInstanceId>,
+    },
 }
 
 impl From<String> for Credential {
@@ -242,6 +291,113 @@ impl Guest for HostComponent {
                     println!("{binding}");
                 }
 
+                Ok(())
+            }
+            #[cfg(feature = "audit")]
+            Commands::Audit { instance_id } => {
+                log(
+                    Level::Info,
+                    "host",
+                    &format!("Audit log for {}", instance_id),
+                );
+
+                let records =
+                    lifecycle_host_extensions::audit::audit_log(&instance_id).map_err(|e| {
+                        log(Level::Error, "host", &format!("Error auditing: {}", e));
+                    })?;
+                println!("{}", records);
+
+                Ok(())
+            }
+            #[cfg(feature = "quotas")]
+            Commands::EnforceQuotas { instance_id } => {
+                log(
+                    Level::Info,
+                    "host",
+                    &format!("Enforcing quotas for {}", instance_id),
+                );
+
+                let usage = lifecycle_host_extensions::quotas::enforce_quotas(&instance_id)
+                    .map_err(|e| {
+                        log(
+                            Level::Error,
+                            "host",
+                            &format!("Error enforcing quotas: {}", e),
+                        );
+                    })?;
+                println!("{}", usage);
+
+                Ok(())
+            }
+            #[cfg(feature = "reconcile")]
+            Commands::Reconcile {
+                instance_id,
+                repair,
+            } => {
+                log(Level::Info, "host", &format!("Reconciling {}", instance_id));
+
+                let discrepancies =
+                    lifecycle_host_extensions::reconcile::reconcile(&instance_id, repair).map_err(
+                        |e| {
+                            log(Level::Error, "host", &format!("Error reconciling: {}", e));
+                        },
+                    )?;
+                println!("{}", discrepancies);
+
+                Ok(())
+            }
+            #[cfg(feature = "archive")]
+            Commands::Export { instance_id } => {
+                log(Level::Info, "host", &format!("Exporting {}", instance_id));
+
+                let chunks = lifecycle_host_extensions::archive::export_archive(&instance_id)
+                    .map_err(|e| {
+                        log(Level::Error, "host", &format!("Error exporting: {}", e));
+                    })?;
+                let stdout = wasi::cli::stdout::get_stdout();
+                for chunk in chunks {
+                    let chunk = chunk.map_err(|e| {
+                        log(Level::Error, "host", &format!("Error exporting: {}", e));
+                    })?;
+                    for chunk in chunk.chunks(4096) {
+                        stdout
+                            .blocking_write_and_flush(chunk)
+                            .expect("failed writing to stdout");
+                    }
+                }
+
+                Ok(())
+            }
+            #[cfg(feature = "archive")]
+            Commands::Import { instance_id } => {
+                let instance_id = match instance_id {
+                    Some(instance_id) => instance_id,
+                    None => UuidIds::generate_instance_id().map_err(|e| {
+                        log(
+                            Level::Error,
+                            "host",
+                            &format!("Error generating instance id: {}", e),
+                        )
+                    })?,
+                };
+                log(Level::Info, "host", &format!("Importing {}", instance_id));
+
+                let stdin = wasi::cli::stdin::get_stdin();
+                let chunks = std::iter::from_fn(|| match stdin.blocking_read(4096) {
+                    Ok(chunk) => Some(Ok(chunk)),
+                    Err(StreamError::Closed) => None,
+                    Err(StreamError::LastOperationFailed(e)) => {
+                        Some(Err(format!("reading archive: {}", e.to_debug_string())))
+                    }
+                });
+                lifecycle_host_extensions::archive::import_archive(&instance_id, chunks).map_err(
+                    |e| {
+                        log(Level::Error, "host", &format!("Error importing: {}", e));
+                    },
+                )?;
+
+                println!("{}", instance_id);
+
                 Ok(())
             }
         }
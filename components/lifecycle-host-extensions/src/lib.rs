@@ -0,0 +1,143 @@
+//! Interfaces a lifecycle may export in addition to the lifecycle, shared by the lifecycle hosts.
+//!
+//! Each interface is behind a feature of the same name. A host built without a feature never calls
+//! the interface, so the interface is not imported by the host and the host can be composed with
+//! lifecycles that do not export it.
+
+pub use componentized::services::types::Error;
+
+/// Archives of service instances, see `componentized:services/archive`.
+#[cfg(feature = "archive")]
+pub mod archive {
+    use crate::componentized::services::archive;
+    use crate::Error;
+
+    /// Size of the chunks an archive is read in.
+    const CHUNK_SIZE: u64 = 64 * 1024;
+
+    /// Chunks of an archive being exported.
+    pub struct ArchiveChunks {
+        reader: archive::ArchiveReader,
+        done: bool,
+    }
+
+    impl Iterator for ArchiveChunks {
+        type Item = Result<Vec<u8>, Error>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.done {
+                return None;
+            }
+            match self.reader.read(CHUNK_SIZE) {
+                Ok(chunk) if chunk.is_empty() => {
+                    self.done = true;
+                    None
+                }
+                Ok(chunk) => Some(Ok(chunk)),
+                Err(e) => {
+                    self.done = true;
+                    Some(Err(e))
+                }
+            }
+        }
+    }
+
+    /// Export the state of a service instance as an archive, read in chunks.
+    pub fn export_archive(instance_id: &str) -> Result<ArchiveChunks, Error> {
+        Ok(ArchiveChunks {
+            reader: archive::export_archive(instance_id)?,
+            done: false,
+        })
+    }
+
+    /// Provision a new service instance from the chunks of an archive. The partially imported
+    /// instance is destroyed if a chunk can not be read.
+    pub fn import_archive(
+        instance_id: &str,
+        chunks: impl IntoIterator<Item = Result<Vec<u8>, Error>>,
+    ) -> Result<(), Error> {
+        let writer = archive::import_archive(instance_id)?;
+        for chunk in chunks {
+            writer.write(&chunk?)?;
+        }
+        writer.finish()
+    }
+}
+
+/// Denied access attempts, see `componentized:services/audit`.
+#[cfg(feature = "audit")]
+pub mod audit {
+    use crate::componentized::services::audit;
+    use crate::Error;
+
+    /// List denied access attempts for the bindings of a service instance as JSON.
+    pub fn audit_log(instance_id: &str) -> Result<String, Error> {
+        let records: Vec<serde_json::Value> = audit::audit_log(instance_id)?
+            .iter()
+            .map(|r| {
+                serde_json::json!({
+                    "binding-id": r.binding_id,
+                    "user": r.user,
+                    "command": r.command,
+                    "key": r.key,
+                    "reason": r.reason,
+                    "age-seconds": r.age_seconds,
+                    "count": r.count,
+                })
+            })
+            .collect();
+        Ok(serde_json::Value::Array(records).to_string())
+    }
+}
+
+/// Quotas of service instances, see `componentized:services/quotas`.
+#[cfg(feature = "quotas")]
+pub mod quotas {
+    use crate::componentized::services::quotas;
+    use crate::Error;
+
+    /// Measure the usage of a service instance and enforce its quota, describing the usage as
+    /// JSON.
+    pub fn enforce_quotas(instance_id: &str) -> Result<String, Error> {
+        let usage = quotas::enforce_quotas(instance_id)?;
+        Ok(serde_json::json!({
+            "max-items": usage.max_items,
+            "max-bytes": usage.max_bytes,
+            "items": usage.items,
+            "bytes": usage.bytes,
+            "restricted": usage.restricted,
+        })
+        .to_string())
+    }
+}
+
+/// Drift between recorded and actual state, see `componentized:services/reconcile`.
+#[cfg(feature = "reconcile")]
+pub mod reconcile {
+    use crate::componentized::services::reconcile;
+    use crate::Error;
+
+    /// Compare the recorded state of a service instance with the service and published
+    /// credentials, optionally repairing each discrepancy, listing the discrepancies as JSON.
+    pub fn reconcile(instance_id: &str, repair: Option<bool>) -> Result<String, Error> {
+        let discrepancies: Vec<serde_json::Value> = reconcile::reconcile(instance_id, repair)?
+            .iter()
+            .map(|d| {
+                serde_json::json!({
+                    "binding-id": d.binding_id,
+                    "kind": d.kind,
+                    "message": d.message,
+                    "repaired": d.repaired,
+                })
+            })
+            .collect();
+        Ok(serde_json::Value::Array(discrepancies).to_string())
+    }
+}
+
+wit_bindgen::generate!({
+    path: "../wit",
+    world: "lifecycle-host-extensions",
+    features: ["clocks-timezone"],
+    generate_all
+});
@@ -3,9 +3,13 @@
 use componentized::services::lifecycle;
 use componentized::services::types::{Request, ServiceBindingId, ServiceInstanceId};
 use exports::wasi::http::incoming_handler::Guest;
+#[cfg(feature = "archive")]
+use wasi::http::types::IncomingBody;
 use wasi::http::types::{
     ErrorCode, Fields, IncomingRequest, OutgoingBody, OutgoingResponse, ResponseOutparam,
 };
+#[cfg(feature = "archive")]
+use wasi::io::streams::{OutputStream, StreamError};
 use wasi::logging::logging::{log, Level};
 
 struct LifecycleHost;
@@ -172,6 +176,118 @@ impl Guest for LifecycleHost {
                     }
                 }
             }
+            #[cfg(feature = "audit")]
+            "/audit" => {
+                let instance_id: ServiceInstanceId = ServiceInstanceId::from(
+                    get_param(&query, "instance-id").unwrap_or("".to_string()),
+                );
+                log(Level::Info, "host", &format!("Audit {instance_id}"));
+                match lifecycle_host_extensions::audit::audit_log(&instance_id) {
+                    Ok(records) => {
+                        ResponseOutparam::set(response_out, Ok(response));
+                        let out = body.write().expect("outgoing stream");
+                        out.blocking_write_and_flush(format!("{}\n", records).as_bytes())
+                            .expect("writing response");
+                    }
+                    Err(e) => {
+                        ResponseOutparam::set(response_out, Err(ErrorCode::InternalError(Some(e))));
+                    }
+                }
+            }
+            #[cfg(feature = "quotas")]
+            "/enforce-quotas" => {
+                let instance_id: ServiceInstanceId = ServiceInstanceId::from(
+                    get_param(&query, "instance-id").unwrap_or("".to_string()),
+                );
+                log(
+                    Level::Info,
+                    "host",
+                    &format!("Enforce quotas {instance_id}"),
+                );
+                match lifecycle_host_extensions::quotas::enforce_quotas(&instance_id) {
+                    Ok(usage) => {
+                        ResponseOutparam::set(response_out, Ok(response));
+                        let out = body.write().expect("outgoing stream");
+                        out.blocking_write_and_flush(format!("{}\n", usage).as_bytes())
+                            .expect("writing response");
+                    }
+                    Err(e) => {
+                        ResponseOutparam::set(response_out, Err(ErrorCode::InternalError(Some(e))));
+                    }
+                }
+            }
+            #[cfg(feature = "reconcile")]
+            "/reconcile" => {
+                let instance_id: ServiceInstanceId = ServiceInstanceId::from(
+                    get_param(&query, "instance-id").unwrap_or("".to_string()),
+                );
+                let repair = get_param(&query, "repair").map(|r| r.parse().unwrap_or(false));
+                log(
+                    Level::Info,
+                    "host",
+                    &format!("Reconcile {instance_id} {repair:?}"),
+                );
+                match lifecycle_host_extensions::reconcile::reconcile(&instance_id, repair) {
+                    Ok(discrepancies) => {
+                        ResponseOutparam::set(response_out, Ok(response));
+                        let out = body.write().expect("outgoing stream");
+                        out.blocking_write_and_flush(format!("{}\n", discrepancies).as_bytes())
+                            .expect("writing response");
+                    }
+                    Err(e) => {
+                        ResponseOutparam::set(response_out, Err(ErrorCode::InternalError(Some(e))));
+                    }
+                }
+            }
+            #[cfg(feature = "archive")]
+            "/export" => {
+                let instance_id: ServiceInstanceId = ServiceInstanceId::from(
+                    get_param(&query, "instance-id").unwrap_or("".to_string()),
+                );
+                log(Level::Info, "host", &format!("Export {instance_id}"));
+                match lifecycle_host_extensions::archive::export_archive(&instance_id) {
+                    Ok(chunks) => {
+                        ResponseOutparam::set(response_out, Ok(response));
+                        let out = body.write().expect("outgoing stream");
+                        for chunk in chunks {
+                            match chunk {
+                                Ok(chunk) => write_all(&out, &chunk),
+                                Err(e) => {
+                                    // the response is underway, the truncated archive fails to
+                                    // import
+                                    log(
+                                        Level::Error,
+                                        "host",
+                                        &format!("Export {instance_id}: {e}"),
+                                    );
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        ResponseOutparam::set(response_out, Err(ErrorCode::InternalError(Some(e))));
+                    }
+                }
+            }
+            #[cfg(feature = "archive")]
+            "/import" => {
+                let instance_id: ServiceInstanceId = ServiceInstanceId::from(
+                    get_param(&query, "instance-id").unwrap_or("".to_string()),
+                );
+                log(Level::Info, "host", &format!("Import {instance_id}"));
+                match import_body(&instance_id, request) {
+                    Ok(_) => {
+                        ResponseOutparam::set(response_out, Ok(response));
+                        let out = body.write().expect("outgoing stream");
+                        out.blocking_write_and_flush(format!("{}\n", instance_id).as_bytes())
+                            .expect("writing response");
+                    }
+                    Err(e) => {
+                        ResponseOutparam::set(response_out, Err(ErrorCode::InternalError(Some(e))));
+                    }
+                }
+            }
             path => {
                 log(Level::Warn, "http", &format!("unmapped path: {path}"));
                 ResponseOutparam::set(
@@ -185,6 +301,36 @@ impl Guest for LifecycleHost {
     }
 }
 
+/// Import the request body, an archive, as it is read.
+#[cfg(feature = "archive")]
+fn import_body(instance_id: &str, request: IncomingRequest) -> Result<(), String> {
+    let incoming = request
+        .consume()
+        .map_err(|_| String::from("request body already consumed"))?;
+    {
+        let stream = incoming
+            .stream()
+            .map_err(|_| String::from("request body stream already taken"))?;
+        let chunks = std::iter::from_fn(|| match stream.blocking_read(4096) {
+            Ok(chunk) => Some(Ok(chunk)),
+            Err(StreamError::Closed) => None,
+            Err(StreamError::LastOperationFailed(e)) => Some(Err(e.to_debug_string())),
+        });
+        lifecycle_host_extensions::archive::import_archive(instance_id, chunks)?;
+    }
+    IncomingBody::finish(incoming);
+    Ok(())
+}
+
+/// Write the response body in chunks, each write is limited to 4096 bytes.
+#[cfg(feature = "archive")]
+fn write_all(out: &OutputStream, contents: &[u8]) {
+    for chunk in contents.chunks(4096) {
+        out.blocking_write_and_flush(chunk)
+            .expect("writing response");
+    }
+}
+
 fn get_param(query: &querystring::QueryParams, key: &str) -> Option<String> {
     for (k, v) in query {
         if *k == key {
@@ -0,0 +1,23 @@
+//! Validation of the ids of service instances and bindings, shared by the components that store
+//! records named after an id.
+//!
+//! Ids are used as file and directory names. Only letters, digits, '-' and '_' are accepted, so
+//! an id stays within its parent directory and never collides with a record the component stores
+//! next to it, such as the `{binding-id}.json` metadata of a binding or a `{id}.tmp` file being
+//! written.
+
+pub type Error = String;
+
+/// Check an id contains only letters, digits, '-' or '_'.
+pub fn validate_id(id: &str) -> Result<(), Error> {
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+    {
+        Err(Error::from(format!(
+            "id '{id}' must contain only letters, digits, '-' or '_'"
+        )))?;
+    }
+    Ok(())
+}
@@ -1,67 +1,609 @@
-#![no_main]
+#![cfg_attr(not(test), no_main)]
 
-use chrono::DateTime;
 use componentized::services::credential_admin::{destroy, publish};
+use componentized::services::credential_store::fetch;
 use componentized::services::types::{Credential, Request, Scope, Tier};
+use componentized::valkey::resp::{self, Value};
 use componentized::valkey::store::{self as valkey};
+use exports::componentized::services::audit::{AuditRecord, Guest as Audit};
 use exports::componentized::services::lifecycle::{
     Error, Guest as Lifecycle, ServiceBindingId, ServiceInstanceId,
 };
-use wasi::clocks::wall_clock::now;
+use exports::componentized::services::quotas::{Guest as Quotas, QuotaUsage};
+use exports::componentized::services::reconcile::{Discrepancy, Guest as Reconcile};
+use metadata::{BindingMetadata, InstanceMetadata};
+use topology::{Topology, Transaction};
 use wasi::config::store::{self as config};
 
-const HOSTNAME_KEY: &str = "hostname";
-const HOSTNAME_DEFAULT: &str = "127.0.0.1";
-const PORT_KEY: &str = "port";
-const PORT_DEFAULT: &str = "6379";
-const USERNAME_KEY: &str = "username";
-const USERNAME_DEFAULT: &str = "default";
-const PASSWORD_KEY: &str = "password";
+mod metadata;
+mod topology;
+
+const NAMESPACE_KEY: &str = "namespace";
+const USERNAME_PREFIX_KEY: &str = "username-prefix";
+const USERNAME_PREFIX_DEFAULT: &str = "binding-";
+const DEFAULT_SCOPE: &str = "read-write";
+const MAX_KEYS_REQUEST: &str = "max-keys";
+const MAX_BYTES_REQUEST: &str = "max-bytes";
+const TIER_FIELD: &str = "tier";
+const RESTRICTED_FIELD: &str = "restricted";
+const SCAN_COUNT: &str = "1000";
+/// ACL LOG returns the 10 most recent entries unless a count is given.
+const ACL_LOG_COUNT: &str = "1024";
+
+/// Quota limits for a tier. Requests may lower, but not raise, the limits of the tier.
+struct TierQuotas {
+    tier: &'static str,
+    max_keys: u64,
+    max_bytes: u64,
+}
+
+const TIER_QUOTAS: &[TierQuotas] = &[
+    TierQuotas {
+        tier: "small",
+        max_keys: 10_000,
+        max_bytes: 64 * 1024 * 1024,
+    },
+    TierQuotas {
+        tier: "medium",
+        max_keys: 100_000,
+        max_bytes: 512 * 1024 * 1024,
+    },
+    TierQuotas {
+        tier: "large",
+        max_keys: 1_000_000,
+        max_bytes: 4 * 1024 * 1024 * 1024,
+    },
+];
+
+/// ACL rules granted to a binding for a scope.
+struct ScopeRules {
+    scope: &'static str,
+    allow: &'static [&'static str],
+}
+
+/// Command categories granted by each binding scope. Every scope is further restricted by
+/// `DENIED_RULES`.
+const SCOPE_RULES: &[ScopeRules] = &[
+    ScopeRules {
+        scope: "read-only",
+        allow: &["+@read", "+@connection"],
+    },
+    ScopeRules {
+        scope: "read-write",
+        allow: &["+@read", "+@write", "+@transaction", "+@connection"],
+    },
+    ScopeRules {
+        scope: "admin",
+        allow: &[
+            "+@read",
+            "+@write",
+            "+@keyspace",
+            "+@transaction",
+            "+@scripting",
+            "+@connection",
+        ],
+    },
+];
+
+/// ACL rules applied after the scope rules for every binding. Dangerous and administrative
+/// commands are never available to a binding, nor are commands that reach beyond the instance's
+/// key prefix (enumerating or counting the whole keyspace, or switching databases). Key patterns
+/// limit access to keys but not the key names `KEYS` and `SCAN` enumerate, so both are denied.
+const DENIED_RULES: &[&str] = &[
+    "-@dangerous",
+    "-@admin",
+    "-keys",
+    "-scan",
+    "-randomkey",
+    "-dbsize",
+    "-select",
+    "-swapdb",
+    "-move",
+];
+
+#[derive(Debug, Clone, Default)]
+struct InstanceQuotas {
+    max_keys: Option<u64>,
+    max_bytes: Option<u64>,
+}
+
+impl InstanceQuotas {
+    fn from_tier(tier: Option<&Tier>, requests: Option<Vec<Request>>) -> Result<Self, Error> {
+        let mut quotas = match tier {
+            None => InstanceQuotas::default(),
+            Some(tier) => match TIER_QUOTAS.iter().find(|t| t.tier == tier) {
+                Some(t) => InstanceQuotas {
+                    max_keys: Some(t.max_keys),
+                    max_bytes: Some(t.max_bytes),
+                },
+                None => {
+                    let tiers: Vec<&str> = TIER_QUOTAS.iter().map(|t| t.tier).collect();
+                    Err(Error::from(format!(
+                        "a tier must be one of: {}",
+                        tiers.join(", ")
+                    )))?
+                }
+            },
+        };
+
+        for Request { key, value } in requests.unwrap_or_default() {
+            let limit = match key.as_str() {
+                MAX_KEYS_REQUEST => &mut quotas.max_keys,
+                MAX_BYTES_REQUEST => &mut quotas.max_bytes,
+                _ => Err(Error::from(format!("request '{key}' is not supported")))?,
+            };
+            let value: u64 = value
+                .parse()
+                .map_err(|_| Error::from(format!("request '{key}' must be an integer")))?;
+            if let Some(max) = *limit {
+                if value > max {
+                    Err(Error::from(format!(
+                        "request '{key}' must not exceed {max} for the tier"
+                    )))?;
+                }
+            }
+            *limit = Some(value);
+        }
+
+        Ok(quotas)
+    }
+
+    fn is_exceeded(&self, keys: u64, bytes: u64) -> bool {
+        self.max_keys.is_some_and(|max| keys > max) || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
 
 #[derive(Debug, Clone)]
 struct ValkeyService {}
 
 impl ValkeyService {
-    fn instances_hash_key() -> String {
-        "instances".to_string()
+    /// Prefix for every key and ACL user managed by the lifecycle, allowing multiple platforms to
+    /// share a Valkey server. Empty unless the `namespace` config value is set.
+    fn namespace() -> Result<String, Error> {
+        let Some(namespace) = config::get(NAMESPACE_KEY)?.filter(|n| !n.is_empty()) else {
+            return Ok(String::new());
+        };
+        if !namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        {
+            Err(Error::from(format!(
+                "'{NAMESPACE_KEY}' may only contain ASCII letters, digits, '-', '_' and '.'"
+            )))?;
+        }
+        Ok(format!("{namespace}:"))
+    }
+    fn instances_hash_key() -> Result<String, Error> {
+        Ok(format!("{}instances", Self::namespace()?))
+    }
+    fn instance_data_key_prefix(instance_id: ServiceInstanceId) -> Result<String, Error> {
+        Ok(format!("{}instances:{instance_id}:", Self::namespace()?))
+    }
+    fn instance_bindings_hash_key(instance_id: ServiceInstanceId) -> Result<String, Error> {
+        Ok(format!("{}instances:{instance_id}", Self::namespace()?))
     }
-    fn instance_data_key_prefix(instance_id: ServiceInstanceId) -> String {
-        format!("instances:{instance_id}:")
+    fn instance_quotas_hash_key(instance_id: ServiceInstanceId) -> Result<String, Error> {
+        Ok(format!("{}quotas:{instance_id}", Self::namespace()?))
     }
-    fn instance_bindings_hash_key(instance_id: ServiceInstanceId) -> String {
-        format!("instances:{instance_id}")
+    /// ACL username for a new binding. The `username-prefix` config value keeps binding users
+    /// apart from the default user and operator accounts.
+    fn binding_username(binding_id: &str) -> Result<String, Error> {
+        let prefix =
+            config::get(USERNAME_PREFIX_KEY)?.unwrap_or(USERNAME_PREFIX_DEFAULT.to_string());
+        Ok(format!("{}{prefix}{binding_id}", Self::namespace()?))
+    }
+    /// ACL username recorded for an existing binding.
+    fn tracked_username(binding_id: &str, binding: &BindingMetadata) -> Result<String, Error> {
+        match &binding.username {
+            Some(username) => Ok(username.clone()),
+            // bindings created before usernames were derived use the binding id
+            None => Ok(format!("{}{binding_id}", Self::namespace()?)),
+        }
     }
 
-    fn connect() -> Result<valkey::Connection, Error> {
-        let host = Self::hostname()?;
-        let port = Self::port()?;
+    /// Scopes granted to a binding. Bindings created before metadata was recorded were granted
+    /// the `read` and `write` categories listed by their published `scopes` credential, they are
+    /// given the scope granting no more than they had and `read-only` when that is unknown.
+    fn binding_scopes(binding_id: &str, binding: &BindingMetadata) -> Vec<Scope> {
+        if !binding.scopes.is_empty() {
+            return binding.scopes.clone();
+        }
+        let legacy_scopes = fetch(binding_id).ok().and_then(|credentials| {
+            credentials
+                .into_iter()
+                .find(|c| c.key == "scopes")
+                .map(|c| c.value)
+        });
+        let mut legacy_scopes: Vec<&str> = legacy_scopes
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .collect();
+        legacy_scopes.sort();
+        legacy_scopes.dedup();
+        match legacy_scopes.as_slice() {
+            ["read", "write"] => vec![Scope::from("read-write")],
+            _ => vec![Scope::from("read-only")],
+        }
+    }
+    fn validate_scopes(scopes: Option<Vec<Scope>>) -> Result<Vec<Scope>, Error> {
+        let scopes = scopes.unwrap_or(vec![Scope::from(DEFAULT_SCOPE)]);
+        if scopes.is_empty() {
+            Err(Error::from("at least one scope is required"))?;
+        }
+        for scope in scopes.iter() {
+            if !SCOPE_RULES.iter().any(|r| r.scope == scope) {
+                let allowed: Vec<&str> = SCOPE_RULES.iter().map(|r| r.scope).collect();
+                Err(Error::from(format!(
+                    "a scope must be one of: {}",
+                    allowed.join(", ")
+                )))?;
+            }
+        }
+        Ok(scopes)
+    }
+    fn acl_rules(password: &str, key_prefix: &str, scopes: &[Scope]) -> Vec<String> {
+        let mut rules = vec![
+            "reset".to_string(),
+            "on".to_string(),
+            format!(">{password}"),
+            format!("~{key_prefix}*"),
+            "resetchannels".to_string(),
+        ];
+        rules.extend(Self::scope_acl_rules(scopes));
+        rules
+    }
+    fn scope_acl_rules(scopes: &[Scope]) -> Vec<String> {
+        let mut rules: Vec<String> = vec![];
+        for scope_rules in SCOPE_RULES.iter() {
+            if scopes.iter().any(|s| s == scope_rules.scope) {
+                for rule in scope_rules.allow {
+                    if !rules.iter().any(|r| r == rule) {
+                        rules.push(rule.to_string());
+                    }
+                }
+            }
+        }
+        rules.extend(DENIED_RULES.iter().map(|r| r.to_string()));
+        rules
+    }
+    fn require_instance(topology: &Topology, instance_id: ServiceInstanceId) -> Result<(), Error> {
+        let instances_key = Self::instances_hash_key()?;
+        if !topology
+            .connect(&instances_key)?
+            .hexists(&instances_key, &instance_id)?
+        {
+            Err(Error::from(format!("instance '{instance_id}' not found")))?;
+        }
+        Ok(())
+    }
 
-        let opts = valkey::HelloOpts {
-            proto_ver: Some("3".to_string()),
-            auth: match config::get(PASSWORD_KEY)? {
-                Some(password) => {
-                    let username: String =
-                        config::get(USERNAME_KEY)?.unwrap_or(USERNAME_DEFAULT.to_string());
-                    Some((username, password))
+    fn save_quotas(
+        topology: &Topology,
+        instance_id: ServiceInstanceId,
+        tier: Option<&Tier>,
+        quotas: &InstanceQuotas,
+    ) -> Result<(), Error> {
+        let key = Self::instance_quotas_hash_key(instance_id)?;
+        let connection = topology.connect(&key)?;
+        let fields = [
+            (TIER_FIELD, tier.cloned()),
+            (MAX_KEYS_REQUEST, quotas.max_keys.map(|v| v.to_string())),
+            (MAX_BYTES_REQUEST, quotas.max_bytes.map(|v| v.to_string())),
+        ];
+        let mut set = vec!["HSET", key.as_str()];
+        let mut unset = vec!["HDEL", key.as_str()];
+        for (field, value) in fields.iter() {
+            match value {
+                Some(value) => set.extend([*field, value.as_str()]),
+                None => unset.push(field),
+            }
+        }
+        for command in [set, unset] {
+            if command.len() > 2 {
+                Self::decode_string(Self::send(connection, &command)?)?;
+            }
+        }
+        Ok(())
+    }
+    fn load_quotas(
+        topology: &Topology,
+        instance_id: ServiceInstanceId,
+    ) -> Result<(Option<Tier>, InstanceQuotas, bool), Error> {
+        let key = Self::instance_quotas_hash_key(instance_id)?;
+        let mut tier = None;
+        let mut quotas = InstanceQuotas::default();
+        let mut restricted = false;
+        for (field, value) in topology.connect(&key)?.hgetall(&key)? {
+            match field.as_str() {
+                TIER_FIELD => tier = Some(value),
+                MAX_KEYS_REQUEST => quotas.max_keys = value.parse().ok(),
+                MAX_BYTES_REQUEST => quotas.max_bytes = value.parse().ok(),
+                RESTRICTED_FIELD => restricted = value == "true",
+                _ => {}
+            }
+        }
+        Ok((tier, quotas, restricted))
+    }
+    /// Count the keys under the instance's data key prefix and the memory they consume.
+    fn measure_usage(
+        topology: &Topology,
+        instance_id: ServiceInstanceId,
+    ) -> Result<(u64, u64), Error> {
+        let pattern = format!("{}*", Self::instance_data_key_prefix(instance_id)?);
+        let mut keys = 0;
+        let mut bytes = 0;
+        for connection in topology.primaries()? {
+            let (k, b) = Self::measure_node_usage(connection, &pattern)?;
+            keys += k;
+            bytes += b;
+        }
+        Ok((keys, bytes))
+    }
+    fn measure_node_usage(
+        connection: &valkey::Connection,
+        pattern: &str,
+    ) -> Result<(u64, u64), Error> {
+        let mut keys = 0;
+        let mut bytes = 0;
+        let mut cursor = "0".to_string();
+        loop {
+            let reply = Self::send(
+                connection,
+                &["SCAN", &cursor, "MATCH", pattern, "COUNT", SCAN_COUNT],
+            )?;
+            let reply = Self::decode_array(reply)?;
+            let (Some(next), Some(page)) = (reply.first(), reply.get(1)) else {
+                Err(Error::from("unexpected SCAN reply"))?
+            };
+            cursor = Self::decode_string(next.clone())?;
+            for key in Self::decode_array(page.clone())? {
+                let key = Self::decode_string(key)?;
+                keys += 1;
+                // keys may expire between the scan and measuring their usage
+                if let Value::Integer(usage) = Self::send(connection, &["MEMORY", "USAGE", &key])? {
+                    bytes += usage.max(0) as u64;
                 }
-                None => None,
+            }
+            if cursor == "0" {
+                break;
+            }
+        }
+        Ok((keys, bytes))
+    }
+    /// Find instance and binding values stored before metadata was recorded, rewriting them when
+    /// `migrate` is set. The legacy records are returned, the instance record as `None`.
+    fn migrate_metadata(
+        topology: &Topology,
+        instance_id: ServiceInstanceId,
+        migrate: bool,
+    ) -> Result<Vec<Option<ServiceBindingId>>, Error> {
+        let instances_key = Self::instances_hash_key()?;
+        let bindings_key = Self::instance_bindings_hash_key(instance_id.clone())?;
+        let mut transaction = Transaction::default();
+        let mut legacy = vec![];
+        if let Some(value) = topology
+            .connect(&instances_key)?
+            .hget(&instances_key, &instance_id)?
+        {
+            if metadata::is_legacy(&value) {
+                let mut instance = InstanceMetadata::parse(&value)?;
+                (instance.tier, _, _) = Self::load_quotas(topology, instance_id.clone())?;
+                let instance = instance.to_json();
+                transaction.key_command(
+                    &instances_key,
+                    &["HSET", &instances_key, &instance_id, &instance],
+                );
+                legacy.push(None);
+            }
+        }
+        for (binding_id, value) in topology.connect(&bindings_key)?.hgetall(&bindings_key)? {
+            let mut binding = BindingMetadata::parse(&value)?;
+            if metadata::is_legacy(&value) || binding.username.is_none() {
+                binding.username = Some(Self::tracked_username(&binding_id, &binding)?);
+                binding.scopes = Self::binding_scopes(&binding_id, &binding);
+                let binding = binding.to_json();
+                transaction.key_command(
+                    &bindings_key,
+                    &["HSET", &bindings_key, &binding_id, &binding],
+                );
+                legacy.push(Some(binding_id));
+            }
+        }
+        if migrate && !legacy.is_empty() {
+            topology.execute(&transaction)?;
+        }
+        Ok(legacy)
+    }
+
+    /// Revoke or restore write access for every binding of the instance.
+    fn restrict_bindings(
+        topology: &Topology,
+        instance_id: ServiceInstanceId,
+        restricted: bool,
+    ) -> Result<(), Error> {
+        let bindings_key = Self::instance_bindings_hash_key(instance_id.clone())?;
+        let quotas_key = Self::instance_quotas_hash_key(instance_id)?;
+        let mut transaction = Transaction::default();
+        for (binding_id, value) in topology.connect(&bindings_key)?.hgetall(&bindings_key)? {
+            let binding = BindingMetadata::parse(&value)?;
+            let rules = match restricted {
+                true => vec!["-@write".to_string()],
+                false => Self::scope_acl_rules(&Self::binding_scopes(&binding_id, &binding)),
+            };
+            let username = Self::tracked_username(&binding_id, &binding)?;
+            transaction.acl_command(&Self::acl_setuser_command(&username, &rules));
+        }
+        transaction.key_command(
+            &quotas_key,
+            &[
+                "HSET",
+                &quotas_key,
+                RESTRICTED_FIELD,
+                &restricted.to_string(),
+            ],
+        );
+        topology.execute(&transaction)
+    }
+
+    /// Credentials published for a binding.
+    fn binding_credentials(
+        topology: &Topology,
+        instance_id: ServiceInstanceId,
+        binding_id: &str,
+        username: &str,
+        password: &str,
+        scopes: &[Scope],
+    ) -> Result<Vec<Credential>, Error> {
+        let key_prefix = Self::instance_data_key_prefix(instance_id.clone())?;
+        let mut credentials = vec![Credential {
+            key: String::from("type"),
+            value: String::from("valkey"),
+        }];
+        credentials.extend(topology.credentials()?);
+        credentials.extend(vec![
+            Credential {
+                key: String::from("username"),
+                value: username.to_string(),
             },
-            client_name: None,
-        };
-        let connection = valkey::connect(&host, port, Some(&opts))?;
+            Credential {
+                key: String::from("password"),
+                value: password.to_string(),
+            },
+            Credential {
+                key: String::from("key-prefix"),
+                value: key_prefix,
+            },
+            Credential {
+                key: String::from("instance-id"),
+                value: instance_id,
+            },
+            Credential {
+                key: String::from("binding-id"),
+                value: binding_id.to_string(),
+            },
+            Credential {
+                key: String::from("scopes"),
+                value: scopes.join(","),
+            },
+            Credential {
+                key: String::from("issued-at"),
+                value: metadata::timestamp(),
+            },
+        ]);
+        Ok(credentials)
+    }
+    /// ACL rules for a binding, without write access while the instance is over quota.
+    fn binding_acl_rules(
+        topology: &Topology,
+        instance_id: ServiceInstanceId,
+        password: &str,
+        scopes: &[Scope],
+    ) -> Result<Vec<String>, Error> {
+        let key_prefix = Self::instance_data_key_prefix(instance_id.clone())?;
+        let mut rules = Self::acl_rules(password, &key_prefix, scopes);
+        let (_, _, restricted) = Self::load_quotas(topology, instance_id)?;
+        if restricted {
+            rules.push("-@write".to_string());
+        }
+        Ok(rules)
+    }
+    /// Recreate the ACL user of an existing binding with a new password on every node, and
+    /// republish its credentials.
+    fn reissue_binding(
+        topology: &Topology,
+        instance_id: ServiceInstanceId,
+        binding_id: &str,
+        username: &str,
+        scopes: &[Scope],
+    ) -> Result<(), Error> {
+        let bindings_key = Self::instance_bindings_hash_key(instance_id.clone())?;
+        let password = topology.connect(&bindings_key)?.acl_genpass()?;
+        let credentials = Self::binding_credentials(
+            topology,
+            instance_id.clone(),
+            binding_id,
+            username,
+            &password,
+            scopes,
+        )?;
+        let rules = Self::binding_acl_rules(topology, instance_id, &password, scopes)?;
+
+        let mut transaction = Transaction::default();
+        transaction.acl_command(&Self::acl_setuser_command(username, &rules));
+        topology.execute(&transaction)?;
+
+        publish(binding_id, credentials.as_slice())
+    }
 
-        Ok(connection)
+    fn acl_setuser_command<'a>(username: &'a str, rules: &'a [String]) -> Vec<&'a str> {
+        let mut command = vec!["ACL", "SETUSER", username];
+        command.extend(rules.iter().map(String::as_str));
+        command
     }
-    fn hostname() -> Result<String, Error> {
-        let hostname = config::get(HOSTNAME_KEY)?.unwrap_or(String::from(HOSTNAME_DEFAULT));
-        Ok(hostname)
+
+    fn send(connection: &valkey::Connection, command: &[&str]) -> Result<Value, Error> {
+        let command: Vec<Value> = command
+            .iter()
+            .map(|arg| Value::BulkString(arg.to_string()))
+            .collect();
+        Ok(connection.send(&command)?)
+    }
+    fn decode_array(value: Value) -> Result<Vec<Value>, Error> {
+        match value {
+            Value::Array(items) | Value::Set(items) | Value::Push(items) => items
+                .iter()
+                .map(|item| {
+                    resp::decode(item).map_err(|e| Error::from(format!("Valkey RESP: {e}")))
+                })
+                .collect(),
+            Value::Null => Ok(vec![]),
+            Value::Error(msg) | Value::BulkError(msg) => Err(Error::from(msg)),
+            value => Err(Error::from(format!("expected an array, got {value:?}"))),
+        }
     }
-    fn port() -> Result<u16, Error> {
-        let port = config::get(PORT_KEY)?.unwrap_or(String::from(PORT_DEFAULT));
-        let port: u16 = port
-            .parse()
-            .map_err(|_| Error::from("port must be an integer"))?;
-        Ok(port)
+    fn decode_string(value: Value) -> Result<String, Error> {
+        match value {
+            Value::String(s) | Value::BulkString(s) | Value::BigNumber(s) => Ok(s),
+            Value::VerbatimString((_, s)) => Ok(s),
+            Value::Integer(i) => Ok(i.to_string()),
+            Value::Double(d) => Ok(d.to_string()),
+            Value::Error(msg) | Value::BulkError(msg) => Err(Error::from(msg)),
+            value => Err(Error::from(format!("expected a string, got {value:?}"))),
+        }
+    }
+    /// Decode a map, or a flat array of alternating keys and values, into string pairs. Values that
+    /// are not strings are skipped.
+    fn decode_fields(value: Value) -> Result<Vec<(String, String)>, Error> {
+        let decode =
+            |v: &Vec<u8>| resp::decode(v).map_err(|e| Error::from(format!("Valkey RESP: {e}")));
+        let mut fields = vec![];
+        match value {
+            Value::Map(pairs) => {
+                for (k, v) in pairs.iter() {
+                    if let (Ok(k), Ok(v)) = (
+                        Self::decode_string(decode(k)?),
+                        Self::decode_string(decode(v)?),
+                    ) {
+                        fields.push((k, v));
+                    }
+                }
+            }
+            value => {
+                let items = Self::decode_array(value)?;
+                for pair in items.chunks(2) {
+                    if let [k, v] = pair {
+                        if let (Ok(k), Ok(v)) = (
+                            Self::decode_string(k.clone()),
+                            Self::decode_string(v.clone()),
+                        ) {
+                            fields.push((k, v));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(fields)
     }
 }
 
@@ -76,51 +618,100 @@ impl Lifecycle for ValkeyService {
             Err(Error::from("only 'valkey' types are supported"))?;
         }
 
-        if tier.is_some() {
-            Err(Error::from("tier is not supported"))?;
-        }
-        if requests.is_some() {
-            Err(Error::from("requests are not supported"))?;
-        }
+        let instance = InstanceMetadata::new(&type_, tier.as_ref(), requests.as_deref())?;
+        let quotas = InstanceQuotas::from_tier(tier.as_ref(), requests)?;
 
-        let connection = Self::connect()?;
+        let topology = Topology::discover()?;
 
-        connection.hset(&Self::instances_hash_key(), &instance_id, "valkey")?;
+        Self::save_quotas(&topology, instance_id.clone(), tier.as_ref(), &quotas)?;
+        let instances_key = Self::instances_hash_key()?;
+        topology.connect(&instances_key)?.hset(
+            &instances_key,
+            &instance_id,
+            &instance.to_json(),
+        )?;
 
         Ok(())
     }
 
     fn update(
-        _instance_id: ServiceInstanceId,
+        instance_id: ServiceInstanceId,
         tier: Option<Tier>,
         requests: Option<Vec<Request>>,
     ) -> Result<(), Error> {
-        if tier.is_some() {
-            Err(Error::from("tier is not supported"))?;
-        }
-        if requests.is_some() {
-            Err(Error::from("requests are not supported"))?;
-        }
+        let topology = Topology::discover()?;
+
+        Self::require_instance(&topology, instance_id.clone())?;
+        Self::migrate_metadata(&topology, instance_id.clone(), true)?;
+
+        // an omitted tier retains the current tier
+        let (current_tier, _, _) = Self::load_quotas(&topology, instance_id.clone())?;
+        let tier = tier.or(current_tier);
+        let instances_key = Self::instances_hash_key()?;
+        let connection = topology.connect(&instances_key)?;
+        let mut instance = InstanceMetadata::parse(
+            &connection
+                .hget(&instances_key, &instance_id)?
+                .unwrap_or_default(),
+        )?;
+        instance.update(tier.as_ref(), requests.as_deref());
+        let quotas = InstanceQuotas::from_tier(tier.as_ref(), requests)?;
+
+        Self::save_quotas(&topology, instance_id.clone(), tier.as_ref(), &quotas)?;
+        connection.hset(&instances_key, &instance_id, &instance.to_json())?;
 
-        // nothing is updatable
         Ok(())
     }
 
     fn destroy(instance_id: ServiceInstanceId, retain: Option<bool>) -> Result<(), Error> {
-        for binding_id in Self::list_bindings(instance_id.clone())? {
-            Self::unbind(binding_id, instance_id.clone())?;
-        }
+        let topology = Topology::discover()?;
 
-        let connection = Self::connect()?;
-
-        connection.hdel(&Self::instances_hash_key(), &instance_id)?;
+        let instances_key = Self::instances_hash_key()?;
+        let bindings_key = Self::instance_bindings_hash_key(instance_id.clone())?;
+        let quotas_key = Self::instance_quotas_hash_key(instance_id.clone())?;
+        let bindings = topology.connect(&bindings_key)?.hgetall(&bindings_key)?;
+        let mut binding_ids = vec![];
+        let mut usernames = vec![];
+        for (binding_id, value) in bindings {
+            let binding = BindingMetadata::parse(&value)?;
+            usernames.push(Self::tracked_username(&binding_id, &binding)?);
+            binding_ids.push(binding_id);
+        }
 
+        let mut transaction = Transaction::default();
+        if !usernames.is_empty() {
+            let mut command = vec!["ACL", "DELUSER"];
+            command.extend(usernames.iter().map(String::as_str));
+            transaction.acl_command(&command);
+        }
+        transaction.key_command(&instances_key, &["HDEL", &instances_key, &instance_id]);
+        transaction.key_command(&quotas_key, &["DEL", &quotas_key]);
         if !retain.unwrap_or(false) {
-            let data_key_prefix = Self::instance_data_key_prefix(instance_id);
-            for key in connection.keys(format!("{data_key_prefix}*").as_str())? {
-                connection.del(&key)?;
+            let data_key_prefix = Self::instance_data_key_prefix(instance_id)?;
+            for connection in topology.primaries()? {
+                transaction.del_keys(&connection.keys(format!("{data_key_prefix}*").as_str())?);
             }
         }
+        topology.execute(&transaction)?;
+        // the bindings are forgotten last, a cluster commits each hash slot on its own so an
+        // interrupted destroy is retried with the bindings still known
+        let mut transaction = Transaction::default();
+        transaction.key_command(&bindings_key, &["DEL", &bindings_key]);
+        topology.execute(&transaction)?;
+
+        // credentials are only destroyed once the bindings are no longer usable, the instance
+        // is already gone so every binding is attempted and the failures reported together
+        let errors: Vec<String> = binding_ids
+            .iter()
+            .filter_map(|binding_id| {
+                destroy(binding_id)
+                    .err()
+                    .map(|e| format!("destroying credentials for {binding_id}: {e}"))
+            })
+            .collect();
+        if !errors.is_empty() {
+            Err(Error::from(errors.join("; ")))?
+        }
 
         Ok(())
     }
@@ -130,92 +721,101 @@ impl Lifecycle for ValkeyService {
         instance_id: ServiceInstanceId,
         scopes: Option<Vec<Scope>>,
     ) -> Result<(), Error> {
-        // default and validate scopes
-        let scopes = scopes.unwrap_or(vec![Scope::from("read"), Scope::from("write")]);
-        for scope in scopes.clone() {
-            if scope != "read" && scope != "write" {
-                Err(Error::from("a scope must be one of: read, write"))?;
-            }
-        }
+        let scopes = Self::validate_scopes(scopes)?;
 
-        let connection = Self::connect()?;
+        let topology = Topology::discover()?;
+        Self::migrate_metadata(&topology, instance_id.clone(), true)?;
 
-        let key_prefix = Self::instance_data_key_prefix(instance_id.clone());
+        let bindings_key = Self::instance_bindings_hash_key(instance_id.clone())?;
+        let connection = topology.connect(&bindings_key)?;
 
-        let hostname = Self::hostname()?;
-        let port = Self::port()?;
-        let username = binding_id.clone();
+        // rebinding keeps the tracked user, a new binding must not take over an existing user
+        let existing = connection.hget(&bindings_key, &binding_id)?;
+        let username = match &existing {
+            Some(value) => Self::tracked_username(&binding_id, &BindingMetadata::parse(value)?)?,
+            None => {
+                let username = Self::binding_username(&binding_id)?;
+                if !matches!(
+                    Self::send(connection, &["ACL", "GETUSER", &username])?,
+                    Value::Null
+                ) {
+                    Err(Error::from(format!(
+                        "ACL user '{username}' already exists and is not managed by this lifecycle"
+                    )))?;
+                }
+                username
+            }
+        };
+        let binding = BindingMetadata::new(&scopes, &username)?.to_json();
         let password = connection.acl_genpass()?;
 
-        let credentials = vec![
-            Credential {
-                key: String::from("type"),
-                value: String::from("valkey"),
-            },
-            Credential {
-                key: String::from("hostname"),
-                value: hostname,
-            },
-            Credential {
-                key: String::from("port"),
-                value: port.to_string(),
-            },
-            Credential {
-                key: String::from("username"),
-                value: username.clone(),
-            },
-            Credential {
-                key: String::from("password"),
-                value: password.clone(),
-            },
-            Credential {
-                key: String::from("key-prefix"),
-                value: key_prefix.clone(),
-            },
-            Credential {
-                key: String::from("instance-id"),
-                value: instance_id.clone(),
-            },
-            Credential {
-                key: String::from("binding-id"),
-                value: binding_id.clone(),
-            },
-            Credential {
-                key: String::from("scopes"),
-                value: scopes.join(","),
-            },
-            Credential {
-                key: String::from("issued-at"),
-                value: DateTime::from_timestamp(now().seconds as i64, 0)
-                    .expect("valid wall clock time")
-                    .format("%Y-%m-%dT%H:%M:%SZ")
-                    .to_string(),
-            },
-        ];
+        let credentials = Self::binding_credentials(
+            &topology,
+            instance_id.clone(),
+            &binding_id,
+            &username,
+            &password,
+            &scopes,
+        )?;
+        let rules = Self::binding_acl_rules(&topology, instance_id.clone(), &password, &scopes)?;
 
         publish(&binding_id, credentials.as_slice())?;
 
-        let mut rules = vec!["on".to_string(), format!(">{password}")];
-        for scope in scopes {
-            rules.push(format!("+@{scope}"));
+        let mut transaction = Transaction::default();
+        transaction.acl_command(&Self::acl_setuser_command(&username, &rules));
+        transaction.key_command(
+            &bindings_key,
+            &["HSET", &bindings_key, &binding_id, &binding],
+        );
+        if let Err(e) = topology.execute(&transaction) {
+            // a rebinding leaves the binding in place, reconcile repairs credentials that no
+            // longer authenticate
+            if existing.is_some() {
+                return Err(e);
+            }
+            // the nodes committed before the failure are reverted on a best effort basis, the
+            // published credentials must not outlive the failed binding
+            let mut compensation = Transaction::default();
+            compensation.acl_command(&["ACL", "DELUSER", &username]);
+            compensation.key_command(&bindings_key, &["HDEL", &bindings_key, &binding_id]);
+            let _ = topology.execute(&compensation);
+            destroy(&binding_id)
+                .map_err(|d| Error::from(format!("{e}; destroying credentials: {d}")))?;
+            Err(e)?;
         }
-        rules.push(format!("~{key_prefix}*"));
-
-        connection.acl_setuser(&username, &rules)?;
-        connection.hset(
-            &Self::instance_bindings_hash_key(instance_id.clone()),
-            &binding_id,
-            "valkey",
-        )?;
 
         Ok(())
     }
 
     fn unbind(binding_id: ServiceBindingId, instance_id: ServiceInstanceId) -> Result<(), Error> {
-        let connection = Self::connect()?;
+        let topology = Topology::discover()?;
+
+        let bindings_key = Self::instance_bindings_hash_key(instance_id.clone())?;
+        let Some(value) = topology
+            .connect(&bindings_key)?
+            .hget(&bindings_key, &binding_id)?
+        else {
+            // an unbind interrupted before destroying the credentials is already unbound, the
+            // credentials are destroyed when they were issued for the instance
+            let issued_for_instance = fetch(&binding_id).is_ok_and(|credentials| {
+                credentials
+                    .iter()
+                    .any(|c| c.key == "instance-id" && c.value == instance_id)
+            });
+            if !issued_for_instance {
+                Err(Error::from(format!(
+                    "binding '{binding_id}' not found for instance '{instance_id}'"
+                )))?;
+            }
+            destroy(&binding_id)?;
+            return Ok(());
+        };
+        let username = Self::tracked_username(&binding_id, &BindingMetadata::parse(&value)?)?;
 
-        connection.acl_deluser(&binding_id)?;
-        connection.hdel(&Self::instance_bindings_hash_key(instance_id), &binding_id)?;
+        let mut transaction = Transaction::default();
+        transaction.acl_command(&["ACL", "DELUSER", &username]);
+        transaction.key_command(&bindings_key, &["HDEL", &bindings_key, &binding_id]);
+        topology.execute(&transaction)?;
 
         destroy(&binding_id)?;
 
@@ -223,7 +823,243 @@ impl Lifecycle for ValkeyService {
     }
 
     fn list_bindings(instance_id: ServiceInstanceId) -> Result<Vec<ServiceBindingId>, Error> {
-        Ok(Self::connect()?.hkeys(&Self::instance_bindings_hash_key(instance_id))?)
+        let bindings_key = Self::instance_bindings_hash_key(instance_id)?;
+        Ok(Topology::discover()?
+            .connect(&bindings_key)?
+            .hkeys(&bindings_key)?)
+    }
+}
+
+impl Quotas for ValkeyService {
+    fn enforce_quotas(instance_id: ServiceInstanceId) -> Result<QuotaUsage, Error> {
+        let topology = Topology::discover()?;
+
+        Self::require_instance(&topology, instance_id.clone())?;
+
+        let (_, quotas, restricted) = Self::load_quotas(&topology, instance_id.clone())?;
+        let (keys, bytes) = Self::measure_usage(&topology, instance_id.clone())?;
+
+        let exceeded = quotas.is_exceeded(keys, bytes);
+        if exceeded != restricted {
+            Self::restrict_bindings(&topology, instance_id, exceeded)?;
+        }
+
+        Ok(QuotaUsage {
+            max_items: quotas.max_keys,
+            max_bytes: quotas.max_bytes,
+            items: keys,
+            bytes,
+            restricted: exceeded,
+        })
+    }
+}
+
+impl Audit for ValkeyService {
+    fn audit_log(instance_id: ServiceInstanceId) -> Result<Vec<AuditRecord>, Error> {
+        let topology = Topology::discover()?;
+
+        Self::require_instance(&topology, instance_id.clone())?;
+
+        let bindings_key = Self::instance_bindings_hash_key(instance_id)?;
+        let mut usernames = vec![];
+        for (binding_id, value) in topology.connect(&bindings_key)?.hgetall(&bindings_key)? {
+            let username = Self::tracked_username(&binding_id, &BindingMetadata::parse(&value)?)?;
+            usernames.push((username, binding_id));
+        }
+
+        // each node keeps its own log of denied commands
+        let mut records = vec![];
+        for connection in topology.nodes()? {
+            let entries = Self::send(connection, &["ACL", "LOG", ACL_LOG_COUNT])?;
+            for entry in Self::decode_array(entries)? {
+                let fields = Self::decode_fields(entry)?;
+                let field = |name: &str| {
+                    fields
+                        .iter()
+                        .find(|(k, _)| k == name)
+                        .map(|(_, v)| v.clone())
+                        .unwrap_or_default()
+                };
+                let user = field("username");
+                let Some((_, binding_id)) = usernames.iter().find(|(u, _)| *u == user) else {
+                    continue;
+                };
+                let reason = field("reason");
+                let object = field("object");
+                // the object is the denied command or key, the command is also in the client info
+                let command = match reason.as_str() {
+                    "command" => object.clone(),
+                    _ => field("client-info")
+                        .split(' ')
+                        .find_map(|f| f.strip_prefix("cmd="))
+                        .unwrap_or_default()
+                        .to_string(),
+                };
+                records.push(AuditRecord {
+                    binding_id: binding_id.clone(),
+                    user,
+                    command,
+                    key: (reason == "key").then_some(object),
+                    reason,
+                    age_seconds: field("age-seconds").parse().unwrap_or_default(),
+                    count: field("count").parse().unwrap_or_default(),
+                });
+            }
+        }
+        records.sort_by(|a, b| a.age_seconds.total_cmp(&b.age_seconds));
+
+        Ok(records)
+    }
+}
+
+impl Reconcile for ValkeyService {
+    fn reconcile(
+        instance_id: ServiceInstanceId,
+        repair: Option<bool>,
+    ) -> Result<Vec<Discrepancy>, Error> {
+        let repair = repair.unwrap_or(false);
+        let topology = Topology::discover()?;
+
+        let instances_key = Self::instances_hash_key()?;
+        let bindings_key = Self::instance_bindings_hash_key(instance_id.clone())?;
+        let quotas_key = Self::instance_quotas_hash_key(instance_id.clone())?;
+        let key_prefix = Self::instance_data_key_prefix(instance_id.clone())?;
+        let bindings = topology.connect(&bindings_key)?.hgetall(&bindings_key)?;
+        let mut discrepancies = vec![];
+
+        // an instance with bindings or quotas that is missing from the instances hash
+        let connection = topology.connect(&instances_key)?;
+        if !connection.hexists(&instances_key, &instance_id)? {
+            if bindings.is_empty() && !topology.connect(&quotas_key)?.exists(&quotas_key)? {
+                Err(Error::from(format!("instance '{instance_id}' not found")))?;
+            }
+            if repair {
+                let (tier, _, _) = Self::load_quotas(&topology, instance_id.clone())?;
+                let instance = InstanceMetadata::new("valkey", tier.as_ref(), None)?.to_json();
+                connection.hset(&instances_key, &instance_id, &instance)?;
+            }
+            discrepancies.push(Discrepancy {
+                binding_id: None,
+                kind: String::from("missing-instance"),
+                message: format!("instance is not recorded in '{instances_key}'"),
+                repaired: repair,
+            });
+        }
+
+        // records stored before metadata was recorded
+        let legacy = Self::migrate_metadata(&topology, instance_id.clone(), repair)?;
+        discrepancies.extend(legacy.into_iter().map(|binding_id| Discrepancy {
+            message: match &binding_id {
+                Some(_) => String::from("binding is recorded without metadata"),
+                None => String::from("instance is recorded without metadata"),
+            },
+            binding_id,
+            kind: String::from("legacy-metadata"),
+            repaired: repair,
+        }));
+
+        let mut usernames = vec![];
+        for (binding_id, value) in bindings {
+            let binding = BindingMetadata::parse(&value)?;
+            let username = Self::tracked_username(&binding_id, &binding)?;
+            let mut found = vec![];
+
+            let mut missing = false;
+            for connection in topology.nodes()? {
+                let user = Self::send(connection, &["ACL", "GETUSER", &username])?;
+                missing |= matches!(user, Value::Null);
+            }
+            if missing {
+                found.push((
+                    "missing-user",
+                    format!("ACL user '{username}' is missing from one or more nodes"),
+                ));
+            }
+            match fetch(&binding_id) {
+                Err(e) => found.push((
+                    "missing-credentials",
+                    format!("credentials could not be fetched: {e}"),
+                )),
+                Ok(credentials) if !missing => {
+                    let credential = |key: &str| {
+                        credentials
+                            .iter()
+                            .find(|c| c.key == key)
+                            .map(|c| c.value.clone())
+                            .unwrap_or_default()
+                    };
+                    if credential("username") != username
+                        || !topology.authenticates(
+                            &bindings_key,
+                            &username,
+                            &credential("password"),
+                        )?
+                    {
+                        found.push((
+                            "stale-credentials",
+                            format!("published credentials do not authenticate as '{username}'"),
+                        ));
+                    }
+                }
+                Ok(_) => {}
+            }
+
+            // recreating the user with a new password repairs the user and its credentials
+            let repaired = repair && !found.is_empty();
+            if repaired {
+                Self::reissue_binding(
+                    &topology,
+                    instance_id.clone(),
+                    &binding_id,
+                    &username,
+                    &Self::binding_scopes(&binding_id, &binding),
+                )?;
+            }
+            discrepancies.extend(found.into_iter().map(|(kind, message)| Discrepancy {
+                binding_id: Some(binding_id.clone()),
+                kind: kind.to_string(),
+                message,
+                repaired,
+            }));
+            usernames.push(username);
+        }
+
+        // users of this lifecycle granted the instance's keys without a binding, users named
+        // otherwise, such as operator accounts, are not managed by the lifecycle
+        let key_pattern = format!("~{key_prefix}*");
+        let username_prefix = Self::binding_username("")?;
+        let mut orphans: Vec<String> = vec![];
+        for connection in topology.nodes()? {
+            for user in Self::decode_array(Self::send(connection, &["ACL", "LIST"])?)? {
+                let user = Self::decode_string(user)?;
+                let rules: Vec<&str> = user.split_whitespace().collect();
+                let [_, username, ..] = rules.as_slice() else {
+                    continue;
+                };
+                if username.starts_with(&username_prefix)
+                    && rules.contains(&key_pattern.as_str())
+                    && !usernames.iter().any(|u| u == username)
+                    && !orphans.iter().any(|u| u == username)
+                {
+                    orphans.push(username.to_string());
+                }
+            }
+        }
+        if repair && !orphans.is_empty() {
+            let mut command = vec!["ACL", "DELUSER"];
+            command.extend(orphans.iter().map(String::as_str));
+            let mut transaction = Transaction::default();
+            transaction.acl_command(&command);
+            topology.execute(&transaction)?;
+        }
+        discrepancies.extend(orphans.into_iter().map(|username| Discrepancy {
+            binding_id: None,
+            kind: String::from("orphan-user"),
+            message: format!("ACL user '{username}' has access to the instance without a binding"),
+            repaired: repair,
+        }));
+
+        Ok(discrepancies)
     }
 }
 
@@ -246,6 +1082,104 @@ impl From<valkey::Error> for Error {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DENIED: [&str; 9] = [
+        "-@dangerous",
+        "-@admin",
+        "-keys",
+        "-scan",
+        "-randomkey",
+        "-dbsize",
+        "-select",
+        "-swapdb",
+        "-move",
+    ];
+
+    fn scope_acl_rules(scopes: &[&str]) -> Vec<String> {
+        let scopes: Vec<Scope> = scopes.iter().map(|s| Scope::from(*s)).collect();
+        ValkeyService::scope_acl_rules(&scopes)
+    }
+
+    fn with_denied(allowed: &[&str]) -> Vec<String> {
+        allowed
+            .iter()
+            .chain(DENIED.iter())
+            .map(|r| r.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn read_only_scope_rules() {
+        assert_eq!(
+            scope_acl_rules(&["read-only"]),
+            with_denied(&["+@read", "+@connection"])
+        );
+    }
+
+    #[test]
+    fn read_write_scope_rules() {
+        assert_eq!(
+            scope_acl_rules(&["read-write"]),
+            with_denied(&["+@read", "+@write", "+@transaction", "+@connection"])
+        );
+    }
+
+    #[test]
+    fn admin_scope_rules() {
+        assert_eq!(
+            scope_acl_rules(&["admin"]),
+            with_denied(&[
+                "+@read",
+                "+@write",
+                "+@keyspace",
+                "+@transaction",
+                "+@scripting",
+                "+@connection",
+            ])
+        );
+    }
+
+    #[test]
+    fn mixed_scope_rules_are_merged_without_duplicates() {
+        assert_eq!(
+            scope_acl_rules(&["read-write", "read-only"]),
+            with_denied(&["+@read", "+@connection", "+@write", "+@transaction"])
+        );
+        assert_eq!(
+            scope_acl_rules(&["admin", "read-only", "admin"]),
+            with_denied(&[
+                "+@read",
+                "+@connection",
+                "+@write",
+                "+@keyspace",
+                "+@transaction",
+                "+@scripting",
+            ])
+        );
+    }
+
+    #[test]
+    fn unknown_scopes_are_only_denied() {
+        assert_eq!(scope_acl_rules(&["superuser"]), with_denied(&[]));
+        assert_eq!(
+            scope_acl_rules(&["read-only", "superuser"]),
+            scope_acl_rules(&["read-only"])
+        );
+        assert_eq!(scope_acl_rules(&[]), with_denied(&[]));
+    }
+
+    #[test]
+    fn denied_rules_follow_the_scope_rules() {
+        for scope in ["read-only", "read-write", "admin"] {
+            let rules = scope_acl_rules(&[scope]);
+            assert!(rules.ends_with(&with_denied(&[])), "{scope}: {rules:?}");
+        }
+    }
+}
+
 wit_bindgen::generate!({
     path: "../wit",
     world: "valkey-lifecycle",
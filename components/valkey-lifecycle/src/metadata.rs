@@ -0,0 +1,212 @@
+use crate::componentized::services::types::{Request, Scope, Tier};
+use crate::exports::componentized::services::lifecycle::Error;
+use crate::wasi::clocks::wall_clock::now;
+use crate::wasi::config::store::{self as config};
+use chrono::DateTime;
+use serde_json::{json, Map, Value as Json};
+use std::collections::BTreeMap;
+
+const LABELS_KEY: &str = "labels";
+
+/// Value stored for bindings created before binding metadata was recorded.
+const LEGACY_BINDING_VALUE: &str = "valkey";
+
+/// Record describing an instance, stored as JSON in the `instances` hash.
+///
+/// Instances provisioned before metadata was recorded store their type as a bare string. They
+/// parse with only the type set and are rewritten by [`InstanceMetadata::to_json`].
+pub(crate) struct InstanceMetadata {
+    pub(crate) type_: String,
+    pub(crate) tier: Option<Tier>,
+    pub(crate) requests: BTreeMap<String, String>,
+    pub(crate) labels: BTreeMap<String, String>,
+    pub(crate) created_at: Option<String>,
+    pub(crate) updated_at: Option<String>,
+}
+
+impl InstanceMetadata {
+    pub(crate) fn new(
+        type_: &str,
+        tier: Option<&Tier>,
+        requests: Option<&[Request]>,
+    ) -> Result<Self, Error> {
+        let timestamp = timestamp();
+        Ok(InstanceMetadata {
+            type_: type_.to_string(),
+            tier: tier.cloned(),
+            requests: requests_map(requests),
+            labels: context_labels()?,
+            created_at: Some(timestamp.clone()),
+            updated_at: Some(timestamp),
+        })
+    }
+
+    /// Record a change to the tier and requests.
+    pub(crate) fn update(&mut self, tier: Option<&Tier>, requests: Option<&[Request]>) {
+        self.tier = tier.cloned();
+        self.requests = requests_map(requests);
+        self.updated_at = Some(timestamp());
+    }
+
+    pub(crate) fn parse(value: &str) -> Result<Self, Error> {
+        if is_legacy(value) {
+            return Ok(InstanceMetadata {
+                type_: value.to_string(),
+                tier: None,
+                requests: BTreeMap::new(),
+                labels: BTreeMap::new(),
+                created_at: None,
+                updated_at: None,
+            });
+        }
+        let fields = parse_object(value)?;
+        Ok(InstanceMetadata {
+            type_: string_field(&fields, "type").unwrap_or_default(),
+            tier: string_field(&fields, "tier"),
+            requests: map_field(&fields, "requests"),
+            labels: map_field(&fields, "labels"),
+            created_at: string_field(&fields, "created-at"),
+            updated_at: string_field(&fields, "updated-at"),
+        })
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        json!({
+            "type": self.type_,
+            "tier": self.tier,
+            "requests": self.requests,
+            "labels": self.labels,
+            "created-at": self.created_at,
+            "updated-at": self.updated_at,
+        })
+        .to_string()
+    }
+}
+
+/// Record describing a binding, stored as JSON in the `instances:{id}` hash.
+///
+/// Bindings created before metadata was recorded store the literal `valkey`. They parse without
+/// scopes, which the lifecycle infers from the credentials published for the binding.
+pub(crate) struct BindingMetadata {
+    pub(crate) scopes: Vec<Scope>,
+    /// ACL user created for the binding, absent for bindings created before usernames were
+    /// derived from the binding id.
+    pub(crate) username: Option<String>,
+    pub(crate) labels: BTreeMap<String, String>,
+    pub(crate) created_at: Option<String>,
+    pub(crate) updated_at: Option<String>,
+}
+
+impl BindingMetadata {
+    pub(crate) fn new(scopes: &[Scope], username: &str) -> Result<Self, Error> {
+        let timestamp = timestamp();
+        Ok(BindingMetadata {
+            scopes: scopes.to_vec(),
+            username: Some(username.to_string()),
+            labels: context_labels()?,
+            created_at: Some(timestamp.clone()),
+            updated_at: Some(timestamp),
+        })
+    }
+
+    pub(crate) fn parse(value: &str) -> Result<Self, Error> {
+        if is_legacy(value) {
+            if value != LEGACY_BINDING_VALUE {
+                Err(Error::from(format!(
+                    "unrecognized binding metadata '{value}'"
+                )))?
+            }
+            return Ok(BindingMetadata {
+                scopes: vec![],
+                username: None,
+                labels: BTreeMap::new(),
+                created_at: None,
+                updated_at: None,
+            });
+        }
+        let fields = parse_object(value)?;
+        let scopes = match fields.get("scopes") {
+            Some(Json::Array(scopes)) => scopes
+                .iter()
+                .filter_map(|s| s.as_str().map(Scope::from))
+                .collect(),
+            _ => vec![],
+        };
+        Ok(BindingMetadata {
+            scopes,
+            username: string_field(&fields, "username"),
+            labels: map_field(&fields, "labels"),
+            created_at: string_field(&fields, "created-at"),
+            updated_at: string_field(&fields, "updated-at"),
+        })
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        json!({
+            "scopes": self.scopes,
+            "username": self.username,
+            "labels": self.labels,
+            "created-at": self.created_at,
+            "updated-at": self.updated_at,
+        })
+        .to_string()
+    }
+}
+
+/// Whether a stored value predates metadata records and needs to be migrated.
+pub(crate) fn is_legacy(value: &str) -> bool {
+    !value.starts_with('{')
+}
+
+/// Current wall clock time, formatted as an RFC 3339 UTC timestamp.
+pub(crate) fn timestamp() -> String {
+    DateTime::from_timestamp(now().seconds as i64, 0)
+        .expect("valid wall clock time")
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+/// Labels describing the context the lifecycle runs in, from the comma separated `key=value` pairs
+/// of the `labels` config value.
+fn context_labels() -> Result<BTreeMap<String, String>, Error> {
+    let mut labels = BTreeMap::new();
+    let Some(value) = config::get(LABELS_KEY)? else {
+        return Ok(labels);
+    };
+    for label in value.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+        let (key, value) = label.split_once('=').ok_or_else(|| {
+            Error::from(format!("label '{label}' must be formatted as key=value"))
+        })?;
+        labels.insert(key.trim().to_string(), value.trim().to_string());
+    }
+    Ok(labels)
+}
+
+fn requests_map(requests: Option<&[Request]>) -> BTreeMap<String, String> {
+    requests
+        .unwrap_or_default()
+        .iter()
+        .map(|r| (r.key.clone(), r.value.clone()))
+        .collect()
+}
+
+fn parse_object(value: &str) -> Result<Map<String, Json>, Error> {
+    match serde_json::from_str(value).map_err(|e| Error::from(e.to_string()))? {
+        Json::Object(fields) => Ok(fields),
+        _ => Err(Error::from("metadata must be a JSON object")),
+    }
+}
+
+fn string_field(fields: &Map<String, Json>, name: &str) -> Option<String> {
+    fields.get(name).and_then(Json::as_str).map(String::from)
+}
+
+fn map_field(fields: &Map<String, Json>, name: &str) -> BTreeMap<String, String> {
+    match fields.get(name) {
+        Some(Json::Object(map)) => map
+            .iter()
+            .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+            .collect(),
+        _ => BTreeMap::new(),
+    }
+}
@@ -0,0 +1,558 @@
+use crate::componentized::services::types::Credential;
+use crate::componentized::valkey::resp::Value;
+use crate::componentized::valkey::store::{self as valkey};
+use crate::exports::componentized::services::lifecycle::Error;
+use crate::wasi::config::store::{self as config};
+use crate::ValkeyService;
+use std::cell::OnceCell;
+
+const TOPOLOGY_KEY: &str = "topology";
+const TOPOLOGY_DEFAULT: &str = "standalone";
+const HOSTNAME_KEY: &str = "hostname";
+const HOSTNAME_DEFAULT: &str = "127.0.0.1";
+const PORT_KEY: &str = "port";
+const PORT_DEFAULT: &str = "6379";
+const USERNAME_KEY: &str = "username";
+const USERNAME_DEFAULT: &str = "default";
+const PASSWORD_KEY: &str = "password";
+const SENTINEL_HOSTS_KEY: &str = "sentinel-hosts";
+const SENTINEL_PRIMARY_NAME_KEY: &str = "sentinel-primary-name";
+const SENTINEL_PRIMARY_NAME_DEFAULT: &str = "mymaster";
+const SENTINEL_USERNAME_KEY: &str = "sentinel-username";
+const SENTINEL_PASSWORD_KEY: &str = "sentinel-password";
+
+const CLUSTER_SLOTS: u16 = 16384;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Standalone,
+    Sentinel,
+    Cluster,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    host: String,
+    port: u16,
+    primary: bool,
+    /// Inclusive ranges of hash slots served by a cluster primary.
+    slots: Vec<(u16, u16)>,
+}
+
+impl Node {
+    fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Whether the node is the primary serving the key.
+    fn serves(&self, key: &str) -> bool {
+        let slot = key_slot(key);
+        self.primary && self.slots.iter().any(|(s, e)| *s <= slot && slot <= *e)
+    }
+}
+
+/// Commands applied together by [`Topology::execute`].
+#[derive(Default)]
+pub(crate) struct Transaction {
+    /// Commands with the key that routes them, ACL commands have no key and run on every node.
+    commands: Vec<(Option<String>, Vec<String>)>,
+    /// Keys to delete after the commands.
+    deletes: Vec<String>,
+}
+
+impl Transaction {
+    /// Queue a command for the primary serving the key.
+    pub(crate) fn key_command(&mut self, key: &str, command: &[&str]) {
+        self.commands.push((
+            Some(key.to_string()),
+            command.iter().map(|arg| arg.to_string()).collect(),
+        ));
+    }
+
+    /// Queue deleting the keys, batched into as few commands as the topology allows.
+    pub(crate) fn del_keys(&mut self, keys: &[String]) {
+        self.deletes.extend(keys.iter().cloned());
+    }
+
+    /// Queue an ACL command for every node.
+    pub(crate) fn acl_command(&mut self, command: &[&str]) {
+        self.commands
+            .push((None, command.iter().map(|arg| arg.to_string()).collect()));
+    }
+}
+
+/// The Valkey servers managed by the lifecycle, as described by the `topology` config value:
+///
+/// - `standalone` (default): a single server at `hostname`:`port`.
+/// - `sentinel`: the primary named `sentinel-primary-name` is discovered from the comma separated
+///   `sentinel-hosts`. ACL changes are applied to the primary and each of its replicas so bindings
+///   survive a failover.
+/// - `cluster`: the nodes are discovered from the seed node at `hostname`:`port`. Keys are read
+///   and written on the primary serving their hash slot, while ACL changes are applied to every
+///   node.
+///
+/// A connection is opened to each node the first time it is used and reused for the remainder of
+/// the lifecycle call.
+pub(crate) struct Topology {
+    mode: Mode,
+    nodes: Vec<Node>,
+    /// Connections to the nodes, by node index.
+    connections: Vec<OnceCell<valkey::Connection>>,
+    sentinels: Vec<(String, u16)>,
+    primary_name: String,
+}
+
+impl Topology {
+    pub(crate) fn discover() -> Result<Self, Error> {
+        let mode = match config::get(TOPOLOGY_KEY)?
+            .unwrap_or(TOPOLOGY_DEFAULT.to_string())
+            .as_str()
+        {
+            "standalone" => Mode::Standalone,
+            "sentinel" => Mode::Sentinel,
+            "cluster" => Mode::Cluster,
+            _ => Err(Error::from(
+                "topology must be one of: standalone, sentinel, cluster",
+            ))?,
+        };
+
+        let mut topology = Topology {
+            mode: mode.clone(),
+            nodes: vec![],
+            connections: vec![],
+            sentinels: vec![],
+            primary_name: String::new(),
+        };
+        let mut seed = None;
+        match mode {
+            Mode::Standalone => {
+                topology.nodes.push(Node {
+                    host: Self::hostname()?,
+                    port: Self::port()?,
+                    primary: true,
+                    slots: vec![(0, CLUSTER_SLOTS - 1)],
+                });
+            }
+            Mode::Sentinel => {
+                let hosts = config::get(SENTINEL_HOSTS_KEY)?.ok_or_else(|| {
+                    Error::from(format!(
+                        "'{SENTINEL_HOSTS_KEY}' config value is required for sentinel topology"
+                    ))
+                })?;
+                topology.sentinels = parse_hosts(&hosts)?;
+                topology.primary_name = config::get(SENTINEL_PRIMARY_NAME_KEY)?
+                    .unwrap_or(SENTINEL_PRIMARY_NAME_DEFAULT.to_string());
+                topology.nodes = topology.discover_sentinel_nodes()?;
+            }
+            Mode::Cluster => {
+                let host = Self::hostname()?;
+                let port = Self::port()?;
+                let connection = valkey::connect(&host, port, Some(&Self::hello_opts()?))?;
+                let nodes = ValkeyService::send(&connection, &["CLUSTER", "NODES"])?;
+                topology.nodes =
+                    parse_cluster_nodes(&ValkeyService::decode_string(nodes)?, &host, port)?;
+                seed = Some((format!("{host}:{port}"), connection));
+            }
+        }
+
+        topology.connections = topology.nodes.iter().map(|_| OnceCell::new()).collect();
+        if let Some((address, connection)) = seed {
+            // reuse the connection to the cluster seed node
+            if let Some(index) = topology.nodes.iter().position(|n| n.address() == address) {
+                let _ = topology.connections[index].set(connection);
+            }
+        }
+
+        Ok(topology)
+    }
+
+    /// Connection to the primary serving the key.
+    pub(crate) fn connect(&self, key: &str) -> Result<&valkey::Connection, Error> {
+        self.connection(self.primary_index(key)?)
+    }
+
+    /// Whether the user is able to authenticate with the primary serving the key.
+    pub(crate) fn authenticates(
+        &self,
+        key: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<bool, Error> {
+        let node = &self.nodes[self.primary_index(key)?];
+        let opts = valkey::HelloOpts {
+            proto_ver: Some("3".to_string()),
+            auth: Some((username.to_string(), password.to_string())),
+            client_name: None,
+        };
+        match valkey::connect(&node.host, node.port, Some(&opts)) {
+            Ok(_) => Ok(true),
+            Err(valkey::Error::Valkey(_)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Connections to each primary, covering the full keyspace.
+    pub(crate) fn primaries(&self) -> Result<Vec<&valkey::Connection>, Error> {
+        (0..self.nodes.len())
+            .filter(|i| self.nodes[*i].primary)
+            .map(|i| self.connection(i))
+            .collect()
+    }
+
+    fn primary_index(&self, key: &str) -> Result<usize, Error> {
+        self.nodes
+            .iter()
+            .position(|n| n.serves(key))
+            .ok_or_else(|| {
+                Error::from(format!("no primary is serving hash slot {}", key_slot(key)))
+            })
+    }
+
+    fn connection(&self, index: usize) -> Result<&valkey::Connection, Error> {
+        let cell = &self.connections[index];
+        if let Some(connection) = cell.get() {
+            return Ok(connection);
+        }
+        let connection = Self::connect_node(&self.nodes[index])?;
+        Ok(cell.get_or_init(|| connection))
+    }
+
+    /// Connections to every node, primaries and replicas.
+    pub(crate) fn nodes(&self) -> Result<Vec<&valkey::Connection>, Error> {
+        (0..self.nodes.len()).map(|i| self.connection(i)).collect()
+    }
+
+    /// Apply the transaction, wrapping the commands sent to each node in MULTI/EXEC.
+    ///
+    /// A node applies its commands atomically: a command rejected while queueing discards every
+    /// command for that node. Standalone and sentinel topologies keep every key on a single
+    /// primary, so key commands are all or nothing. Cluster nodes reject transactions whose keys
+    /// span hash slots, so key commands are grouped by hash slot, ACL commands are grouped on their
+    /// own, and each group commits on its own. Primaries commit before replicas and the first
+    /// failure stops the remaining groups, callers compensate for the groups that did commit.
+    pub(crate) fn execute(&self, transaction: &Transaction) -> Result<(), Error> {
+        let primaries = (0..self.nodes.len()).filter(|i| self.nodes[*i].primary);
+        let replicas = (0..self.nodes.len()).filter(|i| !self.nodes[*i].primary);
+        for index in primaries.chain(replicas) {
+            let node = &self.nodes[index];
+            for commands in self.slot_groups(node, transaction) {
+                Self::exec(self.connection(index)?, &commands)
+                    .map_err(|e| Error::from(format!("transaction on {}: {e}", node.address())))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Hash slot a key command is grouped by, every key shares a group outside of a cluster.
+    fn group_slot(&self, key: Option<&str>) -> Option<u16> {
+        match self.mode {
+            Mode::Cluster => key.map(key_slot),
+            _ => Some(0),
+        }
+    }
+
+    /// Commands for the node, in the order they were queued and grouped by hash slot. Keys to
+    /// delete are batched into a DEL command per hash slot, after the other commands.
+    fn slot_groups(&self, node: &Node, transaction: &Transaction) -> Vec<Vec<Vec<String>>> {
+        let mut groups: Vec<(Option<u16>, Vec<Vec<String>>)> = vec![];
+        let mut group = |slot: Option<u16>| -> usize {
+            match groups.iter().position(|(s, _)| *s == slot) {
+                Some(index) => index,
+                None => {
+                    groups.push((slot, vec![]));
+                    groups.len() - 1
+                }
+            }
+        };
+        let mut commands: Vec<(usize, Vec<String>)> = vec![];
+        for (key, command) in &transaction.commands {
+            if key.as_ref().is_none_or(|key| node.serves(key)) {
+                commands.push((group(self.group_slot(key.as_deref())), command.clone()));
+            }
+        }
+        let mut deletes: Vec<(usize, Vec<String>)> = vec![];
+        for key in transaction.deletes.iter().filter(|key| node.serves(key)) {
+            let index = group(self.group_slot(Some(key)));
+            match deletes.iter_mut().find(|(i, _)| *i == index) {
+                Some((_, command)) => command.push(key.clone()),
+                None => deletes.push((index, vec!["DEL".to_string(), key.clone()])),
+            }
+        }
+        for (index, command) in commands.into_iter().chain(deletes) {
+            groups[index].1.push(command);
+        }
+        groups.into_iter().map(|(_, commands)| commands).collect()
+    }
+
+    fn exec(connection: &valkey::Connection, commands: &[Vec<String>]) -> Result<(), Error> {
+        ValkeyService::decode_string(ValkeyService::send(connection, &["MULTI"])?)?;
+        for command in commands {
+            let command: Vec<&str> = command.iter().map(String::as_str).collect();
+            let queued =
+                ValkeyService::send(connection, &command).and_then(ValkeyService::decode_string);
+            if let Err(e) = queued {
+                ValkeyService::send(connection, &["DISCARD"])?;
+                Err(e)?;
+            }
+        }
+        let replies = match ValkeyService::send(connection, &["EXEC"])? {
+            Value::Null => Err(Error::from("transaction was aborted"))?,
+            replies => ValkeyService::decode_array(replies)?,
+        };
+        // commands failing while executing do not roll back the commands before them
+        for reply in replies {
+            if let Value::Error(msg) | Value::BulkError(msg) = reply {
+                Err(Error::from(msg))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Credentials describing how clients reach the servers.
+    pub(crate) fn credentials(&self) -> Result<Vec<Credential>, Error> {
+        let (hostname, port) = match self.mode {
+            Mode::Sentinel => {
+                let primary = self.nodes.first().expect("sentinel primary");
+                (primary.host.clone(), primary.port)
+            }
+            _ => (Self::hostname()?, Self::port()?),
+        };
+        let mut credentials = vec![
+            Credential {
+                key: String::from("hostname"),
+                value: hostname,
+            },
+            Credential {
+                key: String::from("port"),
+                value: port.to_string(),
+            },
+        ];
+        match self.mode {
+            Mode::Standalone => {}
+            Mode::Sentinel => {
+                let hosts: Vec<String> = self
+                    .sentinels
+                    .iter()
+                    .map(|(host, port)| format!("{host}:{port}"))
+                    .collect();
+                credentials.push(Credential {
+                    key: String::from("sentinel-hosts"),
+                    value: hosts.join(","),
+                });
+                credentials.push(Credential {
+                    key: String::from("sentinel-primary-name"),
+                    value: self.primary_name.clone(),
+                });
+            }
+            Mode::Cluster => {
+                let nodes: Vec<String> = self.nodes.iter().map(Node::address).collect();
+                credentials.push(Credential {
+                    key: String::from("cluster-nodes"),
+                    value: nodes.join(","),
+                });
+            }
+        }
+        Ok(credentials)
+    }
+
+    fn discover_sentinel_nodes(&self) -> Result<Vec<Node>, Error> {
+        let mut last_err = Error::from("no sentinel hosts configured");
+        for (host, port) in self.sentinels.iter() {
+            match self.query_sentinel(host, *port) {
+                Ok(nodes) => return Ok(nodes),
+                Err(e) => last_err = Error::from(format!("sentinel {host}:{port}: {e}")),
+            }
+        }
+        Err(last_err)
+    }
+
+    fn query_sentinel(&self, host: &str, port: u16) -> Result<Vec<Node>, Error> {
+        let opts = valkey::HelloOpts {
+            proto_ver: Some("3".to_string()),
+            auth: match config::get(SENTINEL_PASSWORD_KEY)? {
+                Some(password) => {
+                    let username: String =
+                        config::get(SENTINEL_USERNAME_KEY)?.unwrap_or(USERNAME_DEFAULT.to_string());
+                    Some((username, password))
+                }
+                None => None,
+            },
+            client_name: None,
+        };
+        let connection = valkey::connect(host, port, Some(&opts))?;
+
+        let address = ValkeyService::send(
+            &connection,
+            &["SENTINEL", "GET-MASTER-ADDR-BY-NAME", &self.primary_name],
+        )?;
+        let address = ValkeyService::decode_array(address)?;
+        let (Some(primary_host), Some(primary_port)) = (address.first(), address.get(1)) else {
+            Err(Error::from(format!(
+                "primary '{}' is not known",
+                self.primary_name
+            )))?
+        };
+        let mut nodes = vec![Node {
+            host: ValkeyService::decode_string(primary_host.clone())?,
+            port: parse_port(&ValkeyService::decode_string(primary_port.clone())?)?,
+            primary: true,
+            slots: vec![(0, CLUSTER_SLOTS - 1)],
+        }];
+
+        let replicas =
+            ValkeyService::send(&connection, &["SENTINEL", "REPLICAS", &self.primary_name])?;
+        for replica in ValkeyService::decode_array(replicas)? {
+            let fields = ValkeyService::decode_fields(replica)?;
+            let field = |name: &str| {
+                fields
+                    .iter()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.clone())
+                    .unwrap_or_default()
+            };
+            let flags = field("flags");
+            if flags
+                .split(',')
+                .any(|f| f == "s_down" || f == "o_down" || f == "disconnected")
+            {
+                continue;
+            }
+            nodes.push(Node {
+                host: field("ip"),
+                port: parse_port(&field("port"))?,
+                primary: false,
+                slots: vec![],
+            });
+        }
+
+        Ok(nodes)
+    }
+
+    fn connect_node(node: &Node) -> Result<valkey::Connection, Error> {
+        Ok(valkey::connect(
+            &node.host,
+            node.port,
+            Some(&Self::hello_opts()?),
+        )?)
+    }
+    fn hello_opts() -> Result<valkey::HelloOpts, Error> {
+        Ok(valkey::HelloOpts {
+            proto_ver: Some("3".to_string()),
+            auth: match config::get(PASSWORD_KEY)? {
+                Some(password) => {
+                    let username: String =
+                        config::get(USERNAME_KEY)?.unwrap_or(USERNAME_DEFAULT.to_string());
+                    Some((username, password))
+                }
+                None => None,
+            },
+            client_name: None,
+        })
+    }
+    fn hostname() -> Result<String, Error> {
+        let hostname = config::get(HOSTNAME_KEY)?.unwrap_or(String::from(HOSTNAME_DEFAULT));
+        Ok(hostname)
+    }
+    fn port() -> Result<u16, Error> {
+        parse_port(&config::get(PORT_KEY)?.unwrap_or(String::from(PORT_DEFAULT)))
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, Error> {
+    port.parse()
+        .map_err(|_| Error::from("port must be an integer"))
+}
+
+/// Parse a comma separated list of `host:port` addresses.
+fn parse_hosts(hosts: &str) -> Result<Vec<(String, u16)>, Error> {
+    hosts
+        .split(',')
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+        .map(|h| match h.rsplit_once(':') {
+            Some((host, port)) => Ok((host.to_string(), parse_port(port)?)),
+            None => Err(Error::from(format!("expected host:port, got '{h}'"))),
+        })
+        .collect()
+}
+
+/// Parse the reply of `CLUSTER NODES`, skipping nodes that are failing or have no address.
+/// Each line has the form:
+/// `<id> <ip:port@cport[,hostname]> <flags> <primary> <ping-sent> <pong-recv> <config-epoch> <link-state> <slot> ...`
+fn parse_cluster_nodes(reply: &str, seed_host: &str, seed_port: u16) -> Result<Vec<Node>, Error> {
+    let mut nodes = vec![];
+    for line in reply.lines().filter(|l| !l.trim().is_empty()) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 8 {
+            Err(Error::from(format!(
+                "unexpected CLUSTER NODES line: {line}"
+            )))?;
+        }
+        let flags: Vec<&str> = fields[2].split(',').collect();
+        if flags
+            .iter()
+            .any(|f| ["fail", "fail?", "handshake", "noaddr"].contains(f))
+        {
+            continue;
+        }
+
+        let address = fields[1].split(['@', ',']).next().unwrap_or_default();
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| Error::from(format!("unexpected CLUSTER NODES address: {address}")))?;
+        let (host, port) = match (host, flags.contains(&"myself")) {
+            // a node that has not joined a cluster does not know its own address
+            ("", true) => (seed_host.to_string(), seed_port),
+            _ => (host.to_string(), parse_port(port)?),
+        };
+
+        let mut slots = vec![];
+        // slots being imported or migrated are formatted as `[slot->-node]`, skip them
+        for slot in fields[8..].iter().filter(|s| !s.starts_with('[')) {
+            let (start, end) = slot.split_once('-').unwrap_or((slot, slot));
+            let start: u16 = start
+                .parse()
+                .map_err(|_| Error::from(format!("unexpected slot range: {slot}")))?;
+            let end: u16 = end
+                .parse()
+                .map_err(|_| Error::from(format!("unexpected slot range: {slot}")))?;
+            slots.push((start, end));
+        }
+
+        nodes.push(Node {
+            host,
+            port,
+            primary: flags.contains(&"master"),
+            slots,
+        });
+    }
+    Ok(nodes)
+}
+
+/// Hash slot for a key, honoring `{hash tags}`.
+fn key_slot(key: &str) -> u16 {
+    let mut key = key.as_bytes();
+    if let Some(start) = key.iter().position(|b| *b == b'{') {
+        if let Some(len) = key[start + 1..].iter().position(|b| *b == b'}') {
+            if len > 0 {
+                key = &key[start + 1..start + 1 + len];
+            }
+        }
+    }
+    crc16(key) % CLUSTER_SLOTS
+}
+
+/// CRC16-CCITT (XModem) as used for cluster key hash slots.
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
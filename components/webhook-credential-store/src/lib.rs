@@ -11,6 +11,8 @@ use wasi::http::{
 
 const HOST_KEY: &str = "host";
 const SCHEME_KEY: &str = "scheme";
+const IDS_FIELD: &str = "ids";
+const CURSOR_FIELD: &str = "cursor";
 
 pub(crate) struct WebhookCredentialStore {}
 
@@ -100,6 +102,49 @@ impl Guest for WebhookCredentialStore {
             code => Err(Error::from(format!("unexpected http status {code}"))),
         }
     }
+
+    /// The cursor is sent as the `cursor` header, when set. The response is a JSON object with the
+    /// `ids` of the page, and the `cursor` for the next page or `null`.
+    fn list(cursor: Option<String>) -> Result<(Vec<ServiceId>, Option<String>), Error> {
+        let headers = match &cursor {
+            Some(cursor) => vec![(CURSOR_FIELD, cursor.as_str())],
+            None => vec![],
+        };
+        let response = Self::make_request("/services/credentials/list", headers)?;
+        match response.status() {
+            200 => {
+                let body = response.consume().unwrap();
+                let body_stream = body.stream().unwrap();
+                body_stream.subscribe().block();
+                let page: serde_json::Value = serde_json::from_slice(
+                    body_stream.blocking_read(1024 * 1024).unwrap().as_ref(),
+                )
+                .map_err(|e| e.to_string())?;
+
+                let ids = page[IDS_FIELD]
+                    .as_array()
+                    .and_then(|ids| {
+                        ids.iter()
+                            .map(|id| id.as_str().map(ServiceId::from))
+                            .collect::<Option<Vec<_>>>()
+                    })
+                    .ok_or_else(|| Error::from(format!("'{IDS_FIELD}' must be a list of ids")))?;
+                let next_cursor = match &page[CURSOR_FIELD] {
+                    serde_json::Value::Null => None,
+                    serde_json::Value::String(cursor) => Some(cursor.clone()),
+                    _ => Err(Error::from(format!(
+                        "'{CURSOR_FIELD}' must be a string or null"
+                    )))?,
+                };
+
+                drop(body_stream);
+                drop(body);
+
+                Ok((ids, next_cursor))
+            }
+            code => Err(Error::from(format!("unexpected http status {code}"))),
+        }
+    }
 }
 
 wit_bindgen::generate!({
@@ -27,7 +27,7 @@ binding_id=${read_write_binding_id} componentized_services ops write greeting 'H
 binding_id=${read_write_binding_id} componentized_services ops read greeting
 
 # create read-only binding, capturing the binding_id from stdout
-read_only_binding_id=$(componentized_services bind ${instance_id} --scopes read)
+read_only_binding_id=$(componentized_services bind ${instance_id} --scopes read-only)
 componentized_services credentials fetch ${read_only_binding_id}
 
 # can read previous values, but not write
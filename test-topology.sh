@@ -0,0 +1,173 @@
+#!/bin/bash
+
+# Exercise the valkey lifecycle against sentinel and cluster topologies, and with a namespace,
+# backed by local valkey processes. Requires valkey-server and valkey-cli on the PATH.
+
+set -o errexit
+set -o pipefail
+
+SCRIPT_DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" &> /dev/null && pwd )"
+WASMTIME=${WASMTIME:-wasmtime}
+VALKEY_SERVER=${VALKEY_SERVER:-valkey-server}
+VALKEY_CLI=${VALKEY_CLI:-valkey-cli}
+
+if [ -z ${SKIP_BUILD+x} ]; then
+    ./build.sh
+fi
+
+WORK_DIR="$(mktemp -d)"
+
+cleanup() {
+    for pidfile in "${WORK_DIR}"/*.pid; do
+        [ -f "${pidfile}" ] && kill "$(cat "${pidfile}")" || true
+    done
+    rm -rf "${WORK_DIR}"
+}
+trap cleanup EXIT
+
+topology_config=()
+
+componentized_services() {
+    ${WASMTIME} run -Sconfig -Sinherit-network \
+        -Sconfig-var=path=services \
+        "${topology_config[@]}" \
+        --dir "${SCRIPT_DIR}/tests/testdata"::/ \
+        "${SCRIPT_DIR}/lib/test/cli.wasm" \
+        "$@"
+}
+
+start_server() {
+    local port=$1
+    shift
+    ${VALKEY_SERVER} "$@" \
+        --port "${port}" \
+        --dir "${WORK_DIR}" \
+        --save '' \
+        --appendonly no \
+        --daemonize yes \
+        --pidfile "${WORK_DIR}/${port}.pid" \
+        --logfile "${WORK_DIR}/${port}.log"
+    until ${VALKEY_CLI} -p "${port}" PING &> /dev/null; do
+        sleep 0.1
+    done
+}
+
+expect_user() {
+    local port=$1 username=$2
+    if [ -z "$(${VALKEY_CLI} -p "${port}" ACL GETUSER "${username}")" ]; then
+        echo "expected ACL user ${username} on port ${port}" >&2
+        exit 1
+    fi
+}
+
+expect_no_user() {
+    local port=$1 username=$2
+    if [ -n "$(${VALKEY_CLI} -p "${port}" ACL GETUSER "${username}")" ]; then
+        echo "expected no ACL user ${username} on port ${port}" >&2
+        exit 1
+    fi
+}
+
+expect_credential() {
+    local binding_id=$1 key=$2
+    if ! componentized_services credentials fetch "${binding_id}" | grep -q "\"${key}\""; then
+        echo "expected credential ${key} for ${binding_id}" >&2
+        exit 1
+    fi
+}
+
+# sentinel: a primary, a replica and a sentinel monitoring them
+
+start_server 7000
+start_server 7001 --replicaof 127.0.0.1 7000
+cat > "${WORK_DIR}/sentinel.conf" <<SENTINEL
+sentinel monitor mymaster 127.0.0.1 7000 1
+SENTINEL
+start_server 27000 "${WORK_DIR}/sentinel.conf" --sentinel
+until ${VALKEY_CLI} -p 27000 SENTINEL REPLICAS mymaster | grep -q 7001; do
+    sleep 1
+done
+
+topology_config=(
+    -Sconfig-var=topology=sentinel
+    -Sconfig-var=sentinel-hosts=127.0.0.1:27000
+    -Sconfig-var=sentinel-primary-name=mymaster
+)
+instance_id=$(componentized_services provision --type valkey)
+binding_id=$(componentized_services bind ${instance_id})
+expect_credential ${binding_id} sentinel-hosts
+expect_credential ${binding_id} sentinel-primary-name
+expect_user 7000 binding-${binding_id}
+expect_user 7001 binding-${binding_id}
+componentized_services unbind ${binding_id} ${instance_id}
+expect_no_user 7000 binding-${binding_id}
+expect_no_user 7001 binding-${binding_id}
+componentized_services destroy ${instance_id}
+
+# cluster: three primaries sharing the hash slots
+
+for port in 7100 7101 7102; do
+    start_server ${port} --cluster-enabled yes --cluster-config-file "nodes-${port}.conf"
+done
+${VALKEY_CLI} --cluster create 127.0.0.1:7100 127.0.0.1:7101 127.0.0.1:7102 --cluster-yes
+until ${VALKEY_CLI} -p 7100 CLUSTER INFO | grep -q cluster_state:ok; do
+    sleep 1
+done
+
+topology_config=(
+    -Sconfig-var=topology=cluster
+    -Sconfig-var=port=7100
+)
+instance_id=$(componentized_services provision --type valkey)
+binding_id=$(componentized_services bind ${instance_id})
+expect_credential ${binding_id} cluster-nodes
+componentized_services list-bindings ${instance_id} | grep -q ${binding_id}
+for port in 7100 7101 7102; do
+    expect_user ${port} binding-${binding_id}
+done
+componentized_services unbind ${binding_id} ${instance_id}
+for port in 7100 7101 7102; do
+    expect_no_user ${port} binding-${binding_id}
+done
+componentized_services destroy ${instance_id}
+# destroying an instance with quotas, a binding and data spread over the hash slots removes every
+# key, each hash slot is committed in its own transaction
+instance_id=$(componentized_services provision --type valkey --tier small)
+binding_id=$(componentized_services bind ${instance_id})
+for key in a b c d e f g h; do
+    ${VALKEY_CLI} -c -p 7100 SET "instances:${instance_id}:${key}" "${key}" > /dev/null
+done
+if [ "$(${VALKEY_CLI} -c -p 7100 EXISTS "quotas:${instance_id}")" != "1" ]; then
+    echo "expected quotas for ${instance_id}" >&2
+    exit 1
+fi
+componentized_services destroy ${instance_id}
+for port in 7100 7101 7102; do
+    if [ -n "$(${VALKEY_CLI} -p ${port} KEYS "*${instance_id}*")" ]; then
+        echo "expected no keys of ${instance_id} on port ${port}" >&2
+        exit 1
+    fi
+    expect_no_user ${port} binding-${binding_id}
+done
+if componentized_services credentials fetch ${binding_id} | grep -q password; then
+    echo "expected the credentials of ${binding_id} to be destroyed" >&2
+    exit 1
+fi
+
+# namespace: keys and ACL users of a namespaced lifecycle are kept apart
+
+topology_config=(
+    -Sconfig-var=port=7000
+    -Sconfig-var=namespace=staging
+)
+instance_id=$(componentized_services provision --type valkey)
+binding_id=$(componentized_services bind ${instance_id})
+if [ "$(${VALKEY_CLI} -p 7000 HEXISTS staging:instances ${instance_id})" != "1" ]; then
+    echo "expected instance ${instance_id} in staging:instances" >&2
+    exit 1
+fi
+componentized_services credentials fetch ${binding_id} | grep -q "staging:instances:${instance_id}:"
+expect_user 7000 staging:binding-${binding_id}
+expect_no_user 7000 binding-${binding_id}
+componentized_services destroy ${instance_id}
+expect_no_user 7000 staging:binding-${binding_id}
@@ -14,20 +14,320 @@ if [ -z ${SKIP_BUILD+x} ]; then
 fi
 
 componentized_services() {
+    local config_args=() config_var
+    for config_var in ${config_vars}; do
+        config_args+=(-Sconfig-var="${config_var}")
+    done
     ${WASMTIME} run -Sconfig -Sinherit-network \
-        -Sconfig-var=path=services \
+        -Sconfig-var=path="${path:-services}" \
         --env log_context_kv2fs \
         -Sconfig-var=binding-id="${binding_id}" \
+        "${config_args[@]}" \
         --dir "${SCRIPT_DIR}/tests/testdata"::/ \
         "${SCRIPT_DIR}/lib/test/cli.wasm" \
         $@
 }
 
+lifecycle_host() {
+    ${WASMTIME} run -Sconfig -Sinherit-network \
+        "${SCRIPT_DIR}/lib/test/host-cli-valkey.wasm" \
+        "$@"
+}
+
+filesystem_host() {
+    ${WASMTIME} run -Sconfig \
+        -Sconfig-var=path=services \
+        --dir "${SCRIPT_DIR}/tests/testdata"::/ \
+        "${SCRIPT_DIR}/lib/test/host-cli-filesystem.wasm" \
+        "$@"
+}
+
+config_cache() {
+    ${WASMTIME} run -Sconfig \
+        -Sconfig-var=binding-id=cached \
+        "$@"
+}
+
+valkey_cli() {
+    ${VALKEY_CLI:-valkey-cli} "$@"
+}
+
+# run a command with the valkey lifecycle connecting through a server that counts its connections
+count_valkey_connections() {
+    local count_file
+    count_file="$(mktemp)"
+    rm "${count_file}"
+    python3 "${SCRIPT_DIR}/tests/resp-connection-counter.py" 6380 127.0.0.1:6379 "${count_file}" &
+    local counter_pid=$!
+    until [ -f "${count_file}" ]; do
+        sleep 0.1
+    done
+    local status=0
+    config_vars="hostname=127.0.0.1 port=6380" "$@" > /dev/null || status=$?
+    kill ${counter_pid}
+    if [ "${status}" != "0" ]; then
+        rm -f "${count_file}"
+        return ${status}
+    fi
+    cat "${count_file}"
+    rm "${count_file}"
+}
+
+expect_acl() {
+    local expected=$1 user="binding-$2"
+    shift 2
+    local result
+    result=$(valkey_cli ACL DRYRUN "${user}" "$@")
+    if [ "${expected}" == "allowed" ] && [ "${result}" != "OK" ]; then
+        echo "expected '$*' to be allowed for ${user}: ${result}" >&2
+        exit 1
+    fi
+    if [ "${expected}" == "denied" ] && [ "${result}" == "OK" ]; then
+        echo "expected '$*' to be denied for ${user}" >&2
+        exit 1
+    fi
+}
+
 instance_id=$(componentized_services provision --type "${service_type}")
 componentized_services list-bindings ${instance_id}
 binding_id=$(componentized_services bind ${instance_id})
 componentized_services list-bindings ${instance_id}
 componentized_services credentials fetch ${binding_id}
+if [ "${service_type}" == "valkey" ]; then
+    key_prefix="instances:${instance_id}:"
+    for metadata in "$(valkey_cli HGET instances "${instance_id}")" "$(valkey_cli HGET "instances:${instance_id}" "${binding_id}")"; do
+        if ! echo "${metadata}" | grep -q '"created-at"'; then
+            echo "expected metadata record, got: ${metadata}" >&2
+            exit 1
+        fi
+    done
+    read_only_binding_id=$(componentized_services bind ${instance_id} --scopes read-only)
+    admin_binding_id=$(componentized_services bind ${instance_id} --scopes admin)
+    for user in ${binding_id} ${read_only_binding_id} ${admin_binding_id}; do
+        valkey_cli ACL GETUSER "binding-${user}"
+        expect_acl allowed "${user}" GET "${key_prefix}foo"
+        expect_acl denied "${user}" GET "instances:other:foo"
+        expect_acl denied "${user}" KEYS '*'
+        expect_acl denied "${user}" SCAN 0
+        expect_acl denied "${user}" FLUSHALL
+        expect_acl denied "${user}" CONFIG GET maxmemory
+        expect_acl denied "${user}" SELECT 1
+    done
+    expect_acl allowed "${binding_id}" SET "${key_prefix}foo" bar
+    expect_acl denied "${binding_id}" EVAL 'return 1' 0
+    expect_acl denied "${read_only_binding_id}" SET "${key_prefix}foo" bar
+    expect_acl allowed "${admin_binding_id}" EVAL 'return 1' 0
+    # denied commands are reported in the audit log of the instance
+    password=$(componentized_services credentials fetch ${read_only_binding_id} | grep -A1 '"password"' | sed -n 's/.*value: "\(.*\)",/\1/p')
+    valkey_cli --user "binding-${read_only_binding_id}" --pass "${password}" SET "${key_prefix}foo" bar || true
+    if ! lifecycle_host audit ${instance_id} | grep -q "\"binding-id\":\"${read_only_binding_id}\""; then
+        echo "expected denied SET in the audit log for ${read_only_binding_id}" >&2
+        exit 1
+    fi
+    # users not tracked as bindings of the instance are left untouched
+    if componentized_services unbind default ${instance_id}; then
+        echo "expected unbinding the default user to fail" >&2
+        exit 1
+    fi
+    componentized_services unbind ${read_only_binding_id} ${instance_id}
+    componentized_services unbind ${admin_binding_id} ${instance_id}
+    # retrying an unbind interrupted after the binding was removed destroys its credentials
+    interrupted_binding_id=$(componentized_services bind ${instance_id})
+    valkey_cli ACL DELUSER "binding-${interrupted_binding_id}" > /dev/null
+    valkey_cli HDEL "instances:${instance_id}" "${interrupted_binding_id}" > /dev/null
+    componentized_services unbind ${interrupted_binding_id} ${instance_id}
+    if componentized_services credentials fetch ${interrupted_binding_id}; then
+        echo "expected the credentials of ${interrupted_binding_id} to be destroyed" >&2
+        exit 1
+    fi
+fi
+if [ "${service_type}" == "filesystem" ]; then
+    # bindings without the write scope are flagged read-only and link through the read-only link
+    read_only_binding_id=$(componentized_services bind ${instance_id} --scopes read)
+    if [ "$(readlink "${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}/bindings/${read_only_binding_id}")" != "../read-only" ]; then
+        echo "expected ${read_only_binding_id} to link to the read-only link" >&2
+        exit 1
+    fi
+    componentized_services credentials fetch ${read_only_binding_id} | grep -q '"read-only"'
+    binding_id=${read_only_binding_id} componentized_services ops list /
+    if binding_id=${read_only_binding_id} componentized_services ops write foo 'Hello'; then
+        echo "expected writing with ${read_only_binding_id} to fail" >&2
+        exit 1
+    fi
+    if [ -e "${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}/data/foo" ]; then
+        echo "expected the filesystem client to deny writing with ${read_only_binding_id}" >&2
+        exit 1
+    fi
+    # credentials of further bindings are namespaced, and may be mapped like any other key
+    config_vars="binding-ids=files=${read_only_binding_id}" componentized_services ops write foo 'Hello'
+    if config_vars="binding-ids=files=${read_only_binding_id} rename.files.read-only=read-only" componentized_services ops write foo 'Hello'; then
+        echo "expected writing with the read-only value of the files binding to fail" >&2
+        exit 1
+    fi
+    componentized_services ops delete foo
+    if componentized_services bind ${instance_id} --scopes admin; then
+        echo "expected binding with an unknown scope to fail" >&2
+        exit 1
+    fi
+    componentized_services unbind ${read_only_binding_id} ${instance_id}
+    # bindings with a path scope are rooted at the sub-path of the data directory
+    sub_path_binding_id=$(componentized_services bind ${instance_id} --scopes path=reports/daily)
+    binding_id=${sub_path_binding_id} componentized_services ops write foo 'Hello'
+    if [ ! -f "${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}/data/reports/daily/foo" ]; then
+        echo "expected ${sub_path_binding_id} to write within the reports/daily sub-path" >&2
+        exit 1
+    fi
+    binding_id=${sub_path_binding_id} componentized_services ops delete foo
+    for sub_path in ../other reports/../.. /etc; do
+        if componentized_services bind ${instance_id} --scopes "path=${sub_path}"; then
+            echo "expected binding to path ${sub_path} to fail" >&2
+            exit 1
+        fi
+    done
+    componentized_services unbind ${sub_path_binding_id} ${instance_id}
+    # credentials are mapped to the keys a client expects before being returned as config
+    if config_vars=default.read-only=true componentized_services ops write foo 'Hello'; then
+        echo "expected writing with a default read-only config value to fail" >&2
+        exit 1
+    fi
+    if ! config_vars=template.read-only={type} componentized_services ops write foo 'Hello'; then
+        echo "expected writing with a templated read-only config value to succeed" >&2
+        exit 1
+    fi
+    # upstream config values fall through when missing from the credentials, credentials only
+    # override the keys allowed
+    if config_vars="layers=credentials,config read-only=true" componentized_services ops write bar 'Hello'; then
+        echo "expected writing with an upstream read-only config value to fail" >&2
+        exit 1
+    fi
+    config_vars="read-only=true" componentized_services ops write bar 'Hello'
+    if config_vars="layers=credentials,config credential-overrides=read-only type=keyvalue" componentized_services ops write baz 'Hello'; then
+        echo "expected the upstream type config value not to be overridden" >&2
+        exit 1
+    fi
+    componentized_services ops delete foo
+    componentized_services ops delete bar
+    # credentials are encrypted at rest when keys are configured, recording the id of the key
+    key1=$(printf '%064d' 1)
+    key2=$(printf '%064d' 2)
+    config_vars="encryption-keys=k1=${key1}" componentized_services credentials publish encrypted --credentials password=secret
+    if grep -q secret "${SCRIPT_DIR}/tests/testdata/services/credentials/encrypted" || ! grep -q '"key-id":"k1"' "${SCRIPT_DIR}/tests/testdata/services/credentials/encrypted"; then
+        echo "expected credentials to be encrypted with key k1" >&2
+        exit 1
+    fi
+    # records encrypted with a previous key are readable while the key is listed
+    config_vars="encryption-keys=k1=${key1},k2=${key2}" componentized_services credentials fetch encrypted | grep -q secret
+    if componentized_services credentials fetch encrypted; then
+        echo "expected fetching encrypted credentials without keys to fail" >&2
+        exit 1
+    fi
+    componentized_services credentials destroy encrypted
+    # rotating to a new key re-encrypts records, after which the previous key can be retired.
+    # These records are kept apart so re-encrypting leaves the credentials of other tests alone
+    path=rotation config_vars="encryption-keys=k1=${key1}" componentized_services credentials publish rotated --credentials password=secret
+    if [ "$(path=rotation config_vars="encryption-keys=k1=${key1},k2=${key2} encryption-key-id=k2" componentized_services credentials reencrypt)" != "rotated" ]; then
+        echo "expected rotated to be re-encrypted with key k2" >&2
+        exit 1
+    fi
+    if ! grep -q '"key-id":"k2"' "${SCRIPT_DIR}/tests/testdata/rotation/credentials/rotated" || ! grep -q '"key-id":"k2"' "${SCRIPT_DIR}/tests/testdata/rotation/credential-versions/rotated/1"; then
+        echo "expected credentials and their versions to be encrypted with key k2" >&2
+        exit 1
+    fi
+    path=rotation config_vars="encryption-keys=k2=${key2}" componentized_services credentials fetch rotated | grep -q secret
+    if [ -n "$(path=rotation config_vars="encryption-keys=k1=${key1},k2=${key2} encryption-key-id=k2" componentized_services credentials reencrypt)" ]; then
+        echo "expected records already encrypted with key k2 not to be re-encrypted" >&2
+        exit 1
+    fi
+    # plaintext records are refused once keys are configured, unless explicitly allowed, and
+    # are encrypted by re-encrypting
+    path=rotation componentized_services credentials publish plaintext --credentials password=secret
+    if path=rotation config_vars="encryption-keys=k2=${key2}" componentized_services credentials fetch plaintext; then
+        echo "expected fetching plaintext credentials with keys configured to fail" >&2
+        exit 1
+    fi
+    path=rotation config_vars="encryption-keys=k2=${key2} allow-plaintext=true" componentized_services credentials fetch plaintext | grep -q secret
+    if [ "$(path=rotation config_vars="encryption-keys=k2=${key2}" componentized_services credentials reencrypt)" != "plaintext" ]; then
+        echo "expected plaintext to be encrypted by re-encrypting" >&2
+        exit 1
+    fi
+    if grep -q secret "${SCRIPT_DIR}/tests/testdata/rotation/credentials/plaintext"; then
+        echo "expected plaintext credentials to be encrypted at rest" >&2
+        exit 1
+    fi
+    path=rotation config_vars="encryption-keys=k2=${key2}" componentized_services credentials fetch plaintext | grep -q secret
+    rm -rf "${SCRIPT_DIR}/tests/testdata/rotation"
+    # each publish keeps a version of the credentials, the latest is returned by the store
+    componentized_services credentials publish versioned --credentials password=first
+    componentized_services credentials publish versioned --credentials password=second
+    for version in 1 2; do
+        if [ ! -f "${SCRIPT_DIR}/tests/testdata/services/credential-versions/versioned/${version}" ]; then
+            echo "expected version ${version} of the credentials to be kept" >&2
+            exit 1
+        fi
+    done
+    componentized_services credentials fetch versioned | grep -q second
+    if [ "$(componentized_services credentials list-versions versioned | xargs)" != "1 2" ]; then
+        echo "expected versions 1 and 2 of versioned to be listed" >&2
+        exit 1
+    fi
+    componentized_services credentials fetch-version versioned 1 | grep -q first
+    # rolling back publishes a previous version again, as a new version
+    componentized_services credentials rollback versioned 1
+    componentized_services credentials fetch versioned | grep -q first
+    if [ "$(componentized_services credentials list-versions versioned | xargs)" != "1 2 3" ]; then
+        echo "expected rolling back to add version 3 of versioned" >&2
+        exit 1
+    fi
+    # versions beyond the retention limit are deleted, oldest first
+    config_vars="max-versions=2" componentized_services credentials publish versioned --credentials password=fourth
+    if [ "$(componentized_services credentials list-versions versioned | xargs)" != "3 4" ]; then
+        echo "expected only versions 3 and 4 of versioned to be kept" >&2
+        exit 1
+    fi
+    if componentized_services credentials fetch-version versioned 1; then
+        echo "expected fetching a pruned version to fail" >&2
+        exit 1
+    fi
+    componentized_services credentials fetch-version versioned 3 | grep -q first
+    # ids with credentials are listed once, not once per version
+    if [ "$(componentized_services credentials list | grep -cx versioned)" != "1" ]; then
+        echo "expected versioned to be listed once" >&2
+        exit 1
+    fi
+    componentized_services credentials destroy versioned
+    if componentized_services credentials list | grep -qx versioned; then
+        echo "expected destroyed credentials not to be listed" >&2
+        exit 1
+    fi
+    if [ -e "${SCRIPT_DIR}/tests/testdata/services/credential-versions/versioned" ]; then
+        echo "expected versions of destroyed credentials to be removed" >&2
+        exit 1
+    fi
+    # ids are validated before they are joined onto the base path, rejecting ids that escape it or
+    # name the metadata records stored next to bindings
+    for id in ../escape ../../etc "../instances/${instance_id}" "../credentials/${binding_id}" foo/bar .hidden "${binding_id}.json"; do
+        if componentized_services credentials publish "${id}" --credentials foo=bar; then
+            echo "expected publishing credentials for ${id} to fail" >&2
+            exit 1
+        fi
+        if componentized_services credentials fetch "${id}"; then
+            echo "expected fetching credentials for ${id} to fail" >&2
+            exit 1
+        fi
+        if componentized_services list-bindings "${id}"; then
+            echo "expected listing bindings for ${id} to fail" >&2
+            exit 1
+        fi
+        if componentized_services unbind "${id}" ${instance_id}; then
+            echo "expected unbinding ${id} to fail" >&2
+            exit 1
+        fi
+    done
+    if [ -e "${SCRIPT_DIR}/tests/testdata/services/escape" ]; then
+        echo "expected credentials not to be published outside of the credentials directory" >&2
+        exit 1
+    fi
+fi
 sleep 3
 componentized_services ops write foo 'Hello'
 componentized_services ops list /
@@ -41,3 +341,246 @@ componentized_services ops delete bar
 componentized_services unbind ${binding_id} ${instance_id}
 componentized_services list-bindings ${instance_id}
 componentized_services destroy ${instance_id} --retain false
+if [ "${service_type}" == "filesystem" ]; then
+    # the tier and requests of an instance are recorded by its metadata, and the limits derived
+    # from them as its quotas
+    instance_id=$(componentized_services provision --type filesystem --tier small --requests max-files=10)
+    if ! grep -q '"max-files":10' "${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}/quotas.json"; then
+        echo "expected max-files quota for ${instance_id}" >&2
+        exit 1
+    fi
+    if grep -q '"tier"' "${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}/quotas.json" \
+        || ! grep -q '"tier":"small"' "${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}/instance.json"; then
+        echo "expected the tier of ${instance_id} to be recorded only by its metadata" >&2
+        exit 1
+    fi
+    if componentized_services provision --type filesystem --requests max-files=unlimited; then
+        echo "expected an invalid max-files request to fail" >&2
+        exit 1
+    fi
+    # destroying an instance unbinds its remaining bindings, destroying their credentials
+    binding_ids=()
+    for i in $(seq 2); do
+        binding_ids+=($(componentized_services bind ${instance_id}))
+    done
+    # instances and bindings are described by metadata records, which are not listed as bindings
+    instance_path="${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}"
+    for metadata in "${instance_path}/instance.json" "${instance_path}/bindings/${binding_ids[0]}.json"; do
+        if ! grep -q '"created-at"' "${metadata}"; then
+            echo "expected metadata record ${metadata}" >&2
+            exit 1
+        fi
+    done
+    if [ "$(componentized_services list-bindings ${instance_id} | wc -l)" != "2" ]; then
+        echo "expected 2 bindings for ${instance_id}" >&2
+        exit 1
+    fi
+    # an interrupted unbind is resumed, and metadata records left behind are removed by destroy
+    rm "${instance_path}/bindings/${binding_ids[1]}"
+    componentized_services unbind ${binding_ids[1]} ${instance_id}
+    componentized_services credentials destroy ${binding_ids[1]}
+    echo '{}' > "${instance_path}/bindings/orphan.json"
+    componentized_services destroy ${instance_id} --retain false
+    for binding_id in "${binding_ids[@]}"; do
+        if componentized_services credentials fetch ${binding_id}; then
+            echo "expected credentials for ${binding_id} to be destroyed" >&2
+            exit 1
+        fi
+    done
+    if [ -e "${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}" ]; then
+        echo "expected instance ${instance_id} to be removed" >&2
+        exit 1
+    fi
+    # writes are denied while the files of an instance exceed its quota
+    instance_id=$(filesystem_host provision --type filesystem --requests max-files=2)
+    data_path="${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}/data"
+    binding_id=$(filesystem_host bind ${instance_id})
+    filesystem_host enforce-quotas ${instance_id} | grep -q '"restricted":false'
+    for file in foo bar baz; do
+        binding_id=${binding_id} componentized_services ops write ${file} 'Hello'
+    done
+    usage=$(filesystem_host enforce-quotas ${instance_id})
+    if ! echo "${usage}" | grep -q '"items":3' || ! echo "${usage}" | grep -q '"restricted":true'; then
+        echo "expected ${instance_id} to be restricted over quota, got: ${usage}" >&2
+        exit 1
+    fi
+    if binding_id=${binding_id} componentized_services ops write qux 'Hello'; then
+        echo "expected writing over quota with ${binding_id} to fail" >&2
+        exit 1
+    fi
+    binding_id=${binding_id} componentized_services ops read foo
+    # access is restored once usage is back under quota
+    rm "${data_path}/bar" "${data_path}/baz"
+    usage=$(filesystem_host enforce-quotas ${instance_id})
+    if ! echo "${usage}" | grep -q '"restricted":false'; then
+        echo "expected ${instance_id} to be unrestricted under quota, got: ${usage}" >&2
+        exit 1
+    fi
+    binding_id=${binding_id} componentized_services ops write qux 'Hello'
+    # an exported instance is restored by importing its archive
+    mkdir -p "${data_path}/reports"
+    echo 'Quarterly' > "${data_path}/reports/q1.txt"
+    archive=$(mktemp)
+    filesystem_host export ${instance_id} > "${archive}"
+    filesystem_host destroy ${instance_id} --retain false
+    filesystem_host import --instance-id ${instance_id} < "${archive}"
+    if [ "$(cat "${data_path}/reports/q1.txt")" != "Quarterly" ] || [ ! -f "${data_path}/foo" ] || [ ! -f "${data_path}/qux" ]; then
+        echo "expected the data of ${instance_id} to be restored" >&2
+        exit 1
+    fi
+    if ! grep -q '"max-files":2' "${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}/quotas.json"; then
+        echo "expected the quotas of ${instance_id} to be restored" >&2
+        exit 1
+    fi
+    # importing onto an existing instance fails, leaving the instance intact
+    if filesystem_host import --instance-id ${instance_id} < "${archive}"; then
+        echo "expected importing onto ${instance_id} to fail" >&2
+        exit 1
+    fi
+    if [ "$(cat "${data_path}/reports/q1.txt")" != "Quarterly" ]; then
+        echo "expected ${instance_id} to be intact after a failed import" >&2
+        exit 1
+    fi
+    # a truncated archive is not imported, the partially imported instance is destroyed
+    truncated_instance_id="0badc0de-0000-4000-8000-000000000000"
+    if head -c 1800 "${archive}" | filesystem_host import --instance-id ${truncated_instance_id}; then
+        echo "expected importing a truncated archive to fail" >&2
+        exit 1
+    fi
+    if [ -e "${SCRIPT_DIR}/tests/testdata/services/instances/${truncated_instance_id}" ]; then
+        echo "expected the partially imported ${truncated_instance_id} to be removed" >&2
+        exit 1
+    fi
+    rm "${archive}"
+    filesystem_host destroy ${instance_id} --retain false
+    # new instances are seeded from a template directory
+    mkdir -p "${SCRIPT_DIR}/tests/testdata/services/templates/starter/config"
+    echo 'greeting = "Hello"' > "${SCRIPT_DIR}/tests/testdata/services/templates/starter/config/app.toml"
+    instance_id=$(componentized_services provision --type filesystem --requests template=starter)
+    if [ ! -f "${SCRIPT_DIR}/tests/testdata/services/instances/${instance_id}/data/config/app.toml" ]; then
+        echo "expected instance ${instance_id} to be seeded from the starter template" >&2
+        exit 1
+    fi
+    for template in missing .. ../instances starter/config; do
+        if componentized_services provision --type filesystem --requests "template=${template}"; then
+            echo "expected provisioning from template ${template} to fail" >&2
+            exit 1
+        fi
+    done
+    componentized_services destroy ${instance_id} --retain false
+    rm -rf "${SCRIPT_DIR}/tests/testdata/services/templates"
+fi
+if [ "${service_type}" == "valkey" ]; then
+    # destroying an instance removes the ACL users of its remaining bindings
+    instance_id=$(componentized_services provision --type "${service_type}")
+    binding_ids=()
+    for i in $(seq 5); do
+        binding_ids+=($(componentized_services bind ${instance_id}))
+    done
+    # a lifecycle call reuses a single connection
+    connections=$(count_valkey_connections componentized_services destroy ${instance_id} --retain false)
+    if [ "${connections}" != "1" ]; then
+        echo "expected destroy to open 1 connection, opened ${connections}" >&2
+        exit 1
+    fi
+    for binding_id in "${binding_ids[@]}"; do
+        if [ "$(valkey_cli ACL USERS | grep -cx "binding-${binding_id}")" != "0" ]; then
+            echo "expected ACL user ${binding_id} to be deleted" >&2
+            exit 1
+        fi
+    done
+    # drift between the recorded bindings, the ACL users and the published credentials is
+    # reported and repaired, the host publishes credentials to the valkey credential store
+    instance_id=$(lifecycle_host provision --type valkey)
+    binding_id=$(lifecycle_host bind ${instance_id})
+    key_prefix="instances:${instance_id}:"
+    valkey_cli ACL DELUSER "binding-${binding_id}"
+    valkey_cli ACL SETUSER "binding-orphan-${instance_id}" on "~${key_prefix}*"
+    valkey_cli ACL SETUSER "operator-${instance_id}" on "~${key_prefix}*"
+    # records stored before metadata was recorded are migrated by a repair
+    valkey_cli HSET instances "${instance_id}" valkey
+    valkey_cli HSET "instances:${instance_id}" "legacy-${instance_id}" valkey
+    discrepancies=$(lifecycle_host reconcile ${instance_id})
+    for kind in missing-user orphan-user legacy-metadata; do
+        if ! echo "${discrepancies}" | grep -q "\"kind\":\"${kind}\""; then
+            echo "expected a ${kind} discrepancy, got: ${discrepancies}" >&2
+            exit 1
+        fi
+    done
+    lifecycle_host reconcile ${instance_id} --repair true
+    expect_acl allowed "${binding_id}" GET "${key_prefix}foo"
+    if [ "$(valkey_cli ACL USERS | grep -cx "binding-orphan-${instance_id}")" != "0" ]; then
+        echo "expected orphan ACL user to be deleted" >&2
+        exit 1
+    fi
+    # users not named by the lifecycle are left untouched, even when granted the instance's keys
+    if [ "$(valkey_cli ACL USERS | grep -cx "operator-${instance_id}")" != "1" ]; then
+        echo "expected operator ACL user to be kept" >&2
+        exit 1
+    fi
+    valkey_cli ACL DELUSER "operator-${instance_id}"
+    for metadata in "$(valkey_cli HGET instances "${instance_id}")" "$(valkey_cli HGET "instances:${instance_id}" "legacy-${instance_id}")"; do
+        if ! echo "${metadata}" | grep -q '"scopes"\|"type"'; then
+            echo "expected migrated metadata record, got: ${metadata}" >&2
+            exit 1
+        fi
+    done
+    discrepancies=$(lifecycle_host reconcile ${instance_id})
+    if [ "${discrepancies}" != "[]" ]; then
+        echo "expected no discrepancies after repair, got: ${discrepancies}" >&2
+        exit 1
+    fi
+    lifecycle_host destroy ${instance_id} --retain false
+    # write access is revoked while the keys of an instance exceed its quota
+    instance_id=$(lifecycle_host provision --type valkey --requests max-keys=2)
+    binding_id=$(lifecycle_host bind ${instance_id})
+    key_prefix="instances:${instance_id}:"
+    # a binding created before metadata was recorded, by a lifecycle granting the read category,
+    # is never granted more than it had when access is restored
+    legacy_binding_id="legacy-${instance_id}"
+    valkey_cli ACL SETUSER "${legacy_binding_id}" on ">${legacy_binding_id}" "~${key_prefix}*" +@read
+    valkey_cli HSET "instances:${instance_id}" "${legacy_binding_id}" valkey
+    lifecycle_host enforce-quotas ${instance_id} | grep -q '"restricted":false'
+    expect_acl allowed "${binding_id}" SET "${key_prefix}foo" bar
+    for key in foo bar baz; do
+        valkey_cli SET "${key_prefix}${key}" "${key}"
+    done
+    usage=$(lifecycle_host enforce-quotas ${instance_id})
+    if ! echo "${usage}" | grep -q '"items":3' || ! echo "${usage}" | grep -q '"restricted":true'; then
+        echo "expected ${instance_id} to be restricted over quota, got: ${usage}" >&2
+        exit 1
+    fi
+    expect_acl denied "${binding_id}" SET "${key_prefix}foo" bar
+    expect_acl allowed "${binding_id}" GET "${key_prefix}foo"
+    # access is restored once usage is back under quota
+    valkey_cli DEL "${key_prefix}bar" "${key_prefix}baz"
+    usage=$(lifecycle_host enforce-quotas ${instance_id})
+    if ! echo "${usage}" | grep -q '"restricted":false'; then
+        echo "expected ${instance_id} to be unrestricted under quota, got: ${usage}" >&2
+        exit 1
+    fi
+    expect_acl allowed "${binding_id}" SET "${key_prefix}foo" bar
+    if [ "$(valkey_cli ACL DRYRUN "${legacy_binding_id}" SET "${key_prefix}foo" bar)" == "OK" ]; then
+        echo "expected write access not to be granted to ${legacy_binding_id}" >&2
+        exit 1
+    fi
+    valkey_cli ACL DRYRUN "${legacy_binding_id}" GET "${key_prefix}foo" | grep -qx OK
+    lifecycle_host destroy ${instance_id} --retain false
+fi
+if [ "${service_type}" == "filesystem" ]; then
+    # credentials are reused within the cache TTL, and fetched again once it has elapsed or the
+    # cache is refreshed, the stub credential store counts its fetches
+    reads=$(config_cache -Sconfig-var=cache-ttl-seconds=2 "${SCRIPT_DIR}/lib/test/config-cache.wasm" \
+        get fetches get fetches sleep 3 get fetches refresh get fetches)
+    if [ "$(echo ${reads})" != "fetches=1 fetches=1 fetches=2 fetches=3" ]; then
+        echo "expected credentials to be cached for the TTL, got: ${reads}" >&2
+        exit 1
+    fi
+    # keys configuring credential-config are not passed to clients with the config layer
+    reads=$(config_cache -Sconfig-var=binding-ids=extra=cached -Sconfig-var=layers=credentials,config \
+        "${SCRIPT_DIR}/lib/test/config-cache.wasm" get binding-id get binding-ids get extra.fetches)
+    if [ "$(echo ${reads})" != "binding-id binding-ids extra.fetches=1" ]; then
+        echo "expected binding-id and binding-ids not to be passed to clients, got: ${reads}" >&2
+        exit 1
+    fi
+fi
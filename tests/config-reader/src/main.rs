@@ -0,0 +1,38 @@
+use componentized::services::credential_cache;
+use std::time::Duration;
+use wasi::config::store;
+
+/// Run each step in order:
+/// - `get <key>` prints `<key>=<value>`, or `<key>` if there is no value
+/// - `sleep <seconds>` waits before the next step
+/// - `refresh` refreshes the cached credentials
+fn main() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(step) = args.next() {
+        match step.as_str() {
+            "get" => {
+                let key = args.next().ok_or("get requires a key")?;
+                match store::get(&key).map_err(|e| format!("{e:?}"))? {
+                    Some(value) => println!("{key}={value}"),
+                    None => println!("{key}"),
+                }
+            }
+            "sleep" => {
+                let seconds: u64 = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or("sleep requires a number of seconds")?;
+                std::thread::sleep(Duration::from_secs(seconds));
+            }
+            "refresh" => credential_cache::refresh()?,
+            step => Err(format!("unknown step: {step}"))?,
+        }
+    }
+    Ok(())
+}
+
+wit_bindgen::generate!({
+    path: "../wit",
+    world: "config-reader",
+    generate_all
+});
@@ -0,0 +1,51 @@
+#!/usr/bin/env python3
+
+"""Stand-in valkey server that counts the connections it accepts.
+
+Each connection is relayed to an upstream valkey server, so commands behave as they would against
+the real server. The number of connections accepted so far is written to the count file, which is
+created with a count of 0 once the server is listening.
+
+Usage: resp-connection-counter.py <port> <upstream-host>:<upstream-port> <count-file>
+"""
+
+import socket
+import sys
+import threading
+
+
+def relay(source, destination):
+    try:
+        while data := source.recv(65536):
+            destination.sendall(data)
+    except OSError:
+        pass
+    finally:
+        for sock in (source, destination):
+            try:
+                sock.shutdown(socket.SHUT_RDWR)
+            except OSError:
+                pass
+
+
+def main():
+    port = int(sys.argv[1])
+    upstream_host, upstream_port = sys.argv[2].rsplit(":", 1)
+    count_file = sys.argv[3]
+
+    server = socket.create_server(("127.0.0.1", port), reuse_port=True)
+    count = 0
+    with open(count_file, "w") as f:
+        f.write(f"{count}\n")
+    while True:
+        client, _ = server.accept()
+        count += 1
+        with open(count_file, "w") as f:
+            f.write(f"{count}\n")
+        upstream = socket.create_connection((upstream_host, int(upstream_port)))
+        threading.Thread(target=relay, args=(client, upstream), daemon=True).start()
+        threading.Thread(target=relay, args=(upstream, client), daemon=True).start()
+
+
+if __name__ == "__main__":
+    main()
@@ -1,6 +1,8 @@
 use clap::{Args, Parser, Subcommand};
 use componentized::services::credential_admin::{destroy, publish};
-use componentized::services::credential_store::fetch;
+use componentized::services::credential_encryption::reencrypt;
+use componentized::services::credential_store::{fetch, list};
+use componentized::services::credential_versions::{fetch_version, list_versions};
 use componentized::services::lifecycle;
 use componentized::services::types::{
     Credential, Error, Request, Scope, ServiceBindingId, ServiceId, ServiceInstanceId, Tier,
@@ -141,6 +143,44 @@ enum CredentialCommands {
         id: ServiceId,
     },
 
+    /// List the service instances and bindings with credentials
+    List,
+
+    /// Re-encrypt credentials that are not encrypted with the current key
+    Reencrypt,
+
+    /// List the versions of credentials published for a service instance or binding
+    #[command(arg_required_else_help = true)]
+    ListVersions {
+        /// Identifier for the service instance or binding
+        #[arg(required = true)]
+        id: ServiceId,
+    },
+
+    /// Fetch a version of credentials for a service instance or binding
+    #[command(arg_required_else_help = true)]
+    FetchVersion {
+        /// Identifier for the service instance or binding
+        #[arg(required = true)]
+        id: ServiceId,
+
+        /// Version of the credentials
+        #[arg(required = true)]
+        version: u64,
+    },
+
+    /// Roll back credentials for a service instance or binding by publishing a previous version
+    #[command(arg_required_else_help = true)]
+    Rollback {
+        /// Identifier for the service instance or binding
+        #[arg(required = true)]
+        id: ServiceId,
+
+        /// Version of the credentials to publish again
+        #[arg(required = true)]
+        version: u64,
+    },
+
     /// Export credentials for a service instance or binding as a wasi:config/store component
     #[command(arg_required_else_help = true)]
     Export {
@@ -395,6 +435,69 @@ fn main() -> Result<(), ()> {
 
                 Ok(())
             }
+            CredentialCommands::List => {
+                eprintln!("List creds");
+
+                let mut cursor = None;
+                loop {
+                    let (ids, next_cursor) = list(cursor.as_deref()).map_err(|e: Error| {
+                        eprintln!("Error listing: {}", e);
+                    })?;
+                    for id in ids {
+                        println!("{id}");
+                    }
+                    cursor = next_cursor;
+                    if cursor.is_none() {
+                        break;
+                    }
+                }
+
+                Ok(())
+            }
+            CredentialCommands::Reencrypt => {
+                eprintln!("Re-encrypt creds");
+
+                let ids = reencrypt().map_err(|e: Error| {
+                    eprintln!("Error re-encrypting: {}", e);
+                })?;
+                for id in ids {
+                    println!("{id}");
+                }
+
+                Ok(())
+            }
+            CredentialCommands::ListVersions { id } => {
+                eprintln!("List versions of creds for {}", id);
+
+                let versions = list_versions(&id).map_err(|e: Error| {
+                    eprintln!("Error listing versions of {}: {}", id, e);
+                })?;
+                for version in versions {
+                    println!("{version}");
+                }
+
+                Ok(())
+            }
+            CredentialCommands::FetchVersion { id, version } => {
+                eprintln!("Fetch version {} of creds for {}", version, id);
+
+                let creds = fetch_version(&id, version).map_err(|e: Error| {
+                    eprintln!("Error fetching version {} of {}: {}", version, id, e);
+                })?;
+                println!("{:#?}", creds);
+
+                Ok(())
+            }
+            CredentialCommands::Rollback { id, version } => {
+                eprintln!("Roll back creds for {} to version {}", id, version);
+
+                let creds = fetch_version(&id, version).map_err(|e: Error| {
+                    eprintln!("Error fetching version {} of {}: {}", version, id, e);
+                })?;
+                publish(&id, &creds).map_err(|e: Error| {
+                    eprintln!("Error publishing {}: {}", id, e);
+                })
+            }
             CredentialCommands::Export { id } => {
                 eprintln!("Export creds for {}", id);
 
@@ -1,14 +1,35 @@
 #![no_main]
 
 use exports::componentized::services::credential_store::{Credential, Error, Guest, ServiceId};
+use std::cell::Cell;
 use wasi::logging::logging::{log, Level};
 
+thread_local! {
+    /// Number of times credentials were fetched.
+    static FETCHES: Cell<u64> = const { Cell::new(0) };
+}
+
 pub(crate) struct StubCredentialStore;
 
 impl Guest for StubCredentialStore {
+    /// Credentials count the fetches made, so callers can tell cached credentials from fresh ones.
     fn fetch(id: ServiceId) -> Result<Vec<Credential>, Error> {
         log(Level::Info, "credential-store", &format!("fetch id={id}"));
-        Ok(vec![])
+        let fetches = FETCHES.get() + 1;
+        FETCHES.set(fetches);
+        Ok(vec![Credential {
+            key: String::from("fetches"),
+            value: fetches.to_string(),
+        }])
+    }
+
+    fn list(cursor: Option<String>) -> Result<(Vec<ServiceId>, Option<String>), Error> {
+        log(
+            Level::Info,
+            "credential-store",
+            &format!("list cursor={cursor:?}"),
+        );
+        Ok((vec![], None))
     }
 }
 